@@ -0,0 +1,11 @@
+-- admins are created with `taxonia_api create-admin`
+ALTER TABLE users
+    ADD COLUMN is_admin boolean NOT NULL DEFAULT false;
+
+-- soft delete; rows are removed for good by `taxonia_api purge-deleted-users`
+ALTER TABLE users
+    ADD COLUMN deleted_at timestamptz;
+
+CREATE INDEX idx_users_deleted_at ON users(deleted_at)
+WHERE
+    deleted_at IS NOT NULL;
@@ -0,0 +1,25 @@
+-- local mirror of iNaturalist taxa, filled on demand and by `taxonia_api refresh-taxa`
+CREATE EXTENSION IF NOT EXISTS ltree;
+
+CREATE TABLE taxa(
+    id bigint PRIMARY KEY, -- iNaturalist taxon id
+    parent_id bigint, -- not a foreign key, ancestors may not be mirrored
+    path ltree NOT NULL, -- ids from the root down to this taxon, e.g. 48460.1.2.3
+    rank text,
+    rank_level double precision,
+    name text NOT NULL, -- scientific name
+    common_names jsonb NOT NULL DEFAULT '{}', -- locale -> name
+    default_photo jsonb,
+    iconic_taxon_name text,
+    observations_count bigint,
+    is_active boolean NOT NULL DEFAULT TRUE,
+    -- last full refresh from iNaturalist; NULL for taxa only seen as someone's ancestor
+    fetched_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_taxa_path ON taxa USING GIST(path);
+
+CREATE INDEX idx_taxa_parent_id ON taxa(parent_id);
+
+CREATE INDEX idx_taxa_fetched_at ON taxa(fetched_at);
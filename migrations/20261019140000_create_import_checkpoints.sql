@@ -0,0 +1,7 @@
+-- progress of long-running imports, so an interrupted one can resume
+CREATE TABLE import_checkpoints(
+    source text PRIMARY KEY, -- e.g. dwca:/data/inaturalist-taxonomy.dwca.zip
+    file text NOT NULL, -- file within the source being imported
+    row_number bigint NOT NULL, -- rows of that file already imported
+    updated_at timestamptz NOT NULL DEFAULT now()
+);
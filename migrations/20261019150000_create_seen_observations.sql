@@ -0,0 +1,7 @@
+-- observations shown to a user, so samples can skip them
+CREATE TABLE seen_observations(
+    user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    observation_id bigint NOT NULL, -- iNaturalist observation id
+    seen_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, observation_id)
+);
@@ -0,0 +1,20 @@
+-- quizzes generated by the server, kept so they can be replayed and shared
+CREATE TABLE quiz_sessions(
+    id text PRIMARY KEY, -- random, unguessable; doubles as the share link
+    user_id bigint REFERENCES users(id) ON DELETE SET NULL, -- NULL for anonymous quizzes
+    params jsonb NOT NULL, -- parameters the quiz was generated from
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_quiz_sessions_user_created ON quiz_sessions(user_id, created_at DESC);
+
+CREATE TABLE quiz_questions(
+    session_id text NOT NULL REFERENCES quiz_sessions(id) ON DELETE CASCADE,
+    position integer NOT NULL, -- 0-based order within the quiz
+    observation_id bigint NOT NULL, -- iNaturalist observation the photos are from
+    observer text, -- credited in photo attributions
+    photos jsonb NOT NULL, -- iNaturalist photo records
+    correct_taxon_id bigint NOT NULL,
+    choices jsonb NOT NULL, -- taxa offered as answers, in display order
+    PRIMARY KEY (session_id, position)
+);
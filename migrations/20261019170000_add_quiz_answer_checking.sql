@@ -0,0 +1,18 @@
+-- answers submitted one question at a time and checked by the server
+ALTER TABLE quiz_questions
+    ADD COLUMN chosen_taxon_id bigint,
+    ADD COLUMN is_correct boolean,
+    ADD COLUMN answered_at timestamptz,
+    -- since the previous answer, or since the quiz was created for the first one
+    ADD COLUMN response_ms integer;
+
+ALTER TABLE quiz_sessions
+    ADD COLUMN completed_at timestamptz,
+    ADD COLUMN score double precision; -- fraction answered correctly, set on completion
+
+-- results computed from a quiz session are verified; ones posted by clients are not
+ALTER TABLE quiz_results
+    ADD COLUMN verified boolean NOT NULL DEFAULT FALSE,
+    ADD COLUMN session_id text REFERENCES quiz_sessions(id) ON DELETE SET NULL;
+
+CREATE UNIQUE INDEX idx_quiz_results_session_id ON quiz_results(session_id);
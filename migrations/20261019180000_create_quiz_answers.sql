@@ -0,0 +1,14 @@
+-- one row per question of a saved result, so mistakes can be tracked by taxon
+CREATE TABLE quiz_answers (
+    result_id bigint NOT NULL REFERENCES quiz_results(id) ON DELETE CASCADE,
+    position integer NOT NULL,
+    taxon_id bigint NOT NULL, -- the correct answer
+    chosen_taxon_id bigint, -- null when the question was skipped
+    correct boolean NOT NULL,
+    response_ms integer,
+    observation_id bigint,
+    photo_id bigint,
+    PRIMARY KEY (result_id, position)
+);
+
+CREATE INDEX idx_quiz_answers_taxon_id ON quiz_answers(taxon_id);
@@ -0,0 +1,3 @@
+-- served by /taxa/{id} from the mirror, like the rest of the taxon details;
+-- existing rows get it on their next refresh
+ALTER TABLE taxa ADD COLUMN wikipedia_url text;
@@ -0,0 +1,4 @@
+-- set on every iNaturalist sign-in of an existing user, which already
+-- expected the column
+ALTER TABLE users
+    ADD COLUMN last_login_at timestamptz;
@@ -0,0 +1,189 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+use clap::{Parser, Subcommand};
+
+use crate::clients::inat::InatClient;
+use crate::config::Config;
+use crate::db;
+use crate::repos::taxon_repo::TaxonRepo;
+use crate::repos::user_repo::UserRepo;
+use crate::services::{taxa, taxonomy_import};
+use crate::session_store::SessionStore;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "taxonia_api",
+    version,
+    about = "Taxonia API server and maintenance tasks"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Start the HTTP server (default)
+    Serve,
+    /// Apply pending database migrations and exit
+    Migrate,
+    /// Create an admin user, or promote the user with the given email
+    CreateAdmin {
+        #[arg(long)]
+        display_name: String,
+        #[arg(long)]
+        email: Option<String>,
+    },
+    /// Log a user out everywhere by deleting all of their sessions
+    RevokeSessions {
+        /// Id of the user whose sessions should be revoked
+        #[arg(long)]
+        user: i64,
+    },
+    /// Soft-delete a user and log them out everywhere. They can't sign in
+    /// again, and are removed for good by `purge-deleted-users`.
+    DeleteUser {
+        /// Id of the user to delete
+        #[arg(long)]
+        user: i64,
+    },
+    /// Permanently delete users that were soft-deleted a while ago
+    PurgeDeletedUsers {
+        /// Only purge users deleted at least this many days ago
+        #[arg(long, default_value_t = 30)]
+        older_than_days: i32,
+        /// Print how many users would be purged without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Re-fetch mirrored taxa from iNaturalist, oldest first
+    RefreshTaxa {
+        /// Refresh taxa last fetched at least this many days ago, plus any
+        /// only known as another taxon's ancestor
+        #[arg(long, default_value_t = 30)]
+        older_than_days: i64,
+        /// Maximum number of taxa to refresh in this run
+        #[arg(long, default_value_t = 1000)]
+        limit: i64,
+    },
+    /// Import taxa and common names from iNaturalist's taxonomy export
+    /// (a Darwin Core Archive), resuming an interrupted import
+    ImportTaxonomy {
+        /// The downloaded `.zip`, or the directory it was extracted to
+        path: PathBuf,
+        /// Rows written per batch
+        #[arg(long, default_value_t = 1000)]
+        batch_size: usize,
+        /// Start over instead of resuming from the last checkpoint
+        #[arg(long)]
+        restart: bool,
+    },
+    /// Load and validate the configuration, then exit
+    CheckConfig,
+}
+
+pub async fn migrate(config: &Config) -> Result<()> {
+    let pool = db::connect(config).await?;
+    db::run_migrations(&pool).await
+}
+
+pub async fn create_admin(config: &Config, display_name: &str, email: Option<&str>) -> Result<()> {
+    let pool = db::connect(config).await?;
+    let user_id = UserRepo::new(pool)
+        .create_admin(display_name, email)
+        .await?;
+    println!("admin user id: {user_id}");
+    Ok(())
+}
+
+pub async fn revoke_sessions(config: &Config, user_id: i64) -> Result<()> {
+    let redis = redis::Client::open(config.redis_url.expose_str())?;
+    let revoked = SessionStore::new(redis, config)
+        .revoke_user_sessions(user_id)
+        .await?;
+    println!("revoked {revoked} session(s) for user {user_id}");
+    Ok(())
+}
+
+pub async fn delete_user(config: &Config, user_id: i64) -> Result<()> {
+    let pool = db::connect(config).await?;
+    if !UserRepo::new(pool).soft_delete_user(user_id).await? {
+        bail!("no user {user_id}, or they were already deleted");
+    }
+
+    let redis = redis::Client::open(config.redis_url.expose_str())?;
+    let revoked = SessionStore::new(redis, config)
+        .revoke_user_sessions(user_id)
+        .await?;
+    println!("deleted user {user_id} and revoked {revoked} session(s)");
+    Ok(())
+}
+
+pub async fn purge_deleted_users(
+    config: &Config,
+    older_than_days: i32,
+    dry_run: bool,
+) -> Result<()> {
+    let pool = db::connect(config).await?;
+    let repo = UserRepo::new(pool);
+    let user_ids = repo.list_purgeable_users(older_than_days).await?;
+
+    if dry_run {
+        println!("{} user(s) would be purged", user_ids.len());
+        return Ok(());
+    }
+
+    // log them out first so no session outlives its user
+    let sessions = SessionStore::new(redis::Client::open(config.redis_url.expose_str())?, config);
+    for user_id in &user_ids {
+        sessions.revoke_user_sessions(*user_id).await?;
+    }
+
+    let purged = repo.purge_users(&user_ids).await?;
+    println!("purged {purged} user(s)");
+    Ok(())
+}
+
+pub async fn refresh_taxa(config: &Config, older_than_days: i64, limit: i64) -> Result<()> {
+    let pool = db::connect(config).await?;
+    let redis = redis::Client::open(config.redis_url.expose_str())?;
+    let inat = InatClient::new(config, redis)?;
+    let repo = TaxonRepo::new(pool);
+
+    let before = chrono::Utc::now() - chrono::TimeDelta::days(older_than_days);
+    let ids = repo.list_stale(before, limit).await?;
+    let updated = taxa::refresh_taxa(&repo, &inat, &ids, None).await?;
+    println!("refreshed {updated} of {} taxa", ids.len());
+    Ok(())
+}
+
+pub async fn import_taxonomy(
+    config: &Config,
+    path: &Path,
+    batch_size: usize,
+    restart: bool,
+) -> Result<()> {
+    if batch_size == 0 {
+        bail!("--batch-size must be at least 1");
+    }
+    let pool = db::connect(config).await?;
+    let repo = TaxonRepo::new(pool);
+
+    let summary = taxonomy_import::import_dwca(&repo, path, batch_size, restart).await?;
+    println!(
+        "imported {} taxa and {} common names",
+        summary.taxa, summary.common_names
+    );
+    Ok(())
+}
+
+pub fn check_config(config: &Config) {
+    println!("configuration OK");
+    println!("  app_env: {:?}", config.app_env);
+    println!("  bind_addr: {}", config.bind_addr);
+    println!("  base_url: {}", config.base_url);
+    println!("  run_migrations: {}", config.run_migrations);
+    println!("  db_max_connections: {}", config.db_max_connections);
+    println!("  session_ttl_secs: {}", config.session_ttl_secs);
+}
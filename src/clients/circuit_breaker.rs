@@ -0,0 +1,97 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use prometheus::IntGauge;
+
+/// Stops calls to an upstream after `threshold` consecutive failures, for
+/// `cooldown`. After that a single trial call is let through: success closes
+/// the circuit again, failure reopens it for another cooldown.
+///
+/// Clones share the same state.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    state: Arc<Mutex<State>>,
+    threshold: u32,
+    cooldown: Duration,
+    /// Set to 1 while the circuit is open
+    gauge: IntGauge,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A trial call is in flight. If it never reports back (e.g. the request
+    /// was cancelled), another one is allowed after the cooldown.
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration, gauge: IntGauge) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State::Closed { failures: 0 })),
+            threshold,
+            cooldown,
+            gauge,
+        }
+    }
+
+    /// Whether a call may be attempted now
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match *state {
+            State::Closed { .. } => true,
+            State::Open { until } if now >= until => {
+                *state = State::HalfOpen { since: now };
+                true
+            }
+            State::HalfOpen { since } if now >= since + self.cooldown => {
+                *state = State::HalfOpen { since: now };
+                true
+            }
+            State::Open { .. } | State::HalfOpen { .. } => false,
+        }
+    }
+
+    /// Whether calls are being refused right now. Unlike [`Self::allow`],
+    /// this doesn't claim the trial call once the cooldown is over.
+    pub fn is_open(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match *state {
+            State::Closed { .. } => false,
+            State::Open { until } => now < until,
+            State::HalfOpen { since } => now < since + self.cooldown,
+        }
+    }
+
+    pub fn record_success(&self) {
+        *self.state.lock().unwrap() = State::Closed { failures: 0 };
+        self.gauge.set(0);
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let failures = match *state {
+            State::Closed { failures } => failures + 1,
+            // the trial call failed
+            State::HalfOpen { .. } => self.threshold,
+            State::Open { .. } => return,
+        };
+        if failures >= self.threshold {
+            *state = State::Open {
+                until: Instant::now() + self.cooldown,
+            };
+            self.gauge.set(1);
+        } else {
+            *state = State::Closed { failures };
+        }
+    }
+}
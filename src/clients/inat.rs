@@ -1,16 +1,138 @@
+use crate::clients::circuit_breaker::CircuitBreaker;
+use crate::clients::rate_limiter::{Priority, RateLimiter, Throttled};
+use crate::clients::response_cache::{CachePolicy, ResponseCache};
 use crate::config::Config;
-use anyhow::{Result, anyhow};
-use chrono::{DateTime, Duration, Utc};
-use reqwest::Client;
-use serde::Deserialize;
-use tracing::{debug, info};
+use crate::license::License;
+use crate::metrics::METRICS;
+use crate::request_id::{self, REQUEST_ID_HEADER};
+use crate::secret::Secret;
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use rand::Rng;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{instrument, warn};
 
-static INAT_API_BASE: &str = "https://api.inaturalist.org/v1";
+/// Idle pooled connections are closed after this long
+const POOL_IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(90);
+
+/// Upper bound for a single backoff delay
+const MAX_BACKOFF: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// A `Retry-After` longer than this isn't waited out; the rate limit error is
+/// returned instead, since the client is most likely a waiting user.
+const MAX_RETRY_AFTER: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How long a reachability check is reused. Readiness probes poll much more
+/// often, and every check counts against the rate limit.
+const REACHABILITY_TTL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Most results iNaturalist returns per page
+pub const MAX_PER_PAGE: usize = 30;
+
+const HOUR: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+const DAY: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// Autocomplete results shift as names and observation counts change
+const AUTOCOMPLETE_CACHE: CachePolicy = CachePolicy {
+    fresh: HOUR.saturating_mul(6),
+    stale: DAY.saturating_mul(7),
+};
+/// Taxonomy changes are rare, and a day-old name or photo is harmless
+const TAXA_SHOW_CACHE: CachePolicy = CachePolicy {
+    fresh: DAY,
+    stale: DAY.saturating_mul(30),
+};
+/// Place names and boundaries almost never change
+const PLACES_CACHE: CachePolicy = CachePolicy {
+    fresh: DAY.saturating_mul(7),
+    stale: DAY.saturating_mul(30),
+};
+/// Checklists grow slowly as observations are added
+const SPECIES_COUNTS_CACHE: CachePolicy = CachePolicy {
+    fresh: DAY,
+    stale: DAY.saturating_mul(7),
+};
+
+/// Failed iNaturalist call, classified so callers can react to each case
+#[derive(Debug, thiserror::Error)]
+pub enum InatError {
+    #[error("rate limited by iNaturalist")]
+    RateLimited {
+        retry_after: Option<std::time::Duration>,
+    },
+    #[error("iNaturalist rejected the credentials ({0})")]
+    Unauthorized(StatusCode),
+    #[error("iNaturalist rejected the request ({0})")]
+    Rejected(StatusCode),
+    #[error("iNaturalist server error ({0})")]
+    Server(StatusCode),
+    #[error("iNaturalist request timed out")]
+    Timeout,
+    #[error("could not reach iNaturalist: {0}")]
+    Network(#[source] reqwest::Error),
+    #[error("iNaturalist is unavailable, not sending requests for now")]
+    CircuitOpen,
+    /// Our own rate limit, see [`RateLimiter`]
+    #[error("too many iNaturalist requests queued, try again shortly")]
+    Throttled,
+    #[error("daily iNaturalist request budget used up")]
+    DailyBudgetExhausted,
+}
+
+impl InatError {
+    fn from_status(status: StatusCode, retry_after: Option<std::time::Duration>) -> Self {
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => InatError::RateLimited { retry_after },
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => InatError::Unauthorized(status),
+            s if s.is_server_error() => InatError::Server(status),
+            _ => InatError::Rejected(status),
+        }
+    }
+
+    /// Label for the `inat_requests_total` metric
+    fn outcome(&self) -> &'static str {
+        match self {
+            InatError::RateLimited { .. } => "rate_limited",
+            InatError::Unauthorized(_) => "unauthorized",
+            InatError::Rejected(_) => "rejected",
+            InatError::Server(_) => "server_error",
+            InatError::Timeout => "timeout",
+            InatError::Network(_) => "network_error",
+            InatError::CircuitOpen => "circuit_open",
+            InatError::Throttled => "throttled",
+            InatError::DailyBudgetExhausted => "daily_budget_exhausted",
+        }
+    }
+
+    /// Failures that suggest iNaturalist is down, as opposed to refusing
+    /// this particular request. Only these count towards the circuit breaker.
+    fn is_outage(&self) -> bool {
+        matches!(
+            self,
+            InatError::Server(_) | InatError::Timeout | InatError::Network(_)
+        )
+    }
+}
+
+/// Whether a request may be repeated after a failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Retry {
+    /// Safe to send again, e.g. a `GET`
+    Idempotent,
+    /// Only retried when the connection failed, so the request can't have
+    /// reached iNaturalist (e.g. exchanging a single-use OAuth code)
+    ConnectOnly,
+}
 
 #[derive(Debug)]
 pub struct TokenWithExpiry {
-    pub access_token: String,
-    pub refresh_token: Option<String>,
+    pub access_token: Secret<String>,
+    pub refresh_token: Option<Secret<String>>,
     pub expires_at: Option<DateTime<Utc>>,
 }
 
@@ -23,46 +145,390 @@ pub struct InatUser {
     pub email: Option<String>,
 }
 
+/// Taxon as returned by the iNaturalist API. Which fields are present
+/// depends on the endpoint, so nearly all are optional.
+#[derive(Debug, Deserialize, Clone)]
+pub struct InatTaxon {
+    pub id: i64,
+    pub name: String,
+    pub rank: Option<String>,
+    pub rank_level: Option<f64>,
+    pub iconic_taxon_name: Option<String>,
+    pub preferred_common_name: Option<String>,
+    /// Slash-separated ids from the root down to the parent
+    pub ancestry: Option<String>,
+    /// Ids from the root down to, and including, the taxon itself
+    pub ancestor_ids: Option<Vec<i64>>,
+    pub ancestors: Option<Vec<InatTaxon>>,
+    pub default_photo: Option<InatPhoto>,
+    /// Only included with `all_names=true`
+    pub names: Option<Vec<InatTaxonName>>,
+    pub observations_count: Option<i64>,
+    pub wikipedia_url: Option<String>,
+    pub is_active: Option<bool>,
+}
+
+impl InatTaxon {
+    /// Ids from the root down to the parent
+    pub fn ancestor_ids(&self) -> Vec<i64> {
+        match (&self.ancestry, &self.ancestor_ids) {
+            (Some(ancestry), _) => ancestry
+                .split('/')
+                .filter_map(|id| id.parse().ok())
+                .collect(),
+            // this form includes the taxon itself
+            (None, Some(ids)) => ids.iter().copied().filter(|&id| id != self.id).collect(),
+            (None, None) => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InatPhoto {
+    pub id: i64,
+    pub url: Option<String>,
+    pub square_url: Option<String>,
+    pub medium_url: Option<String>,
+    pub attribution: Option<String>,
+    pub license_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct InatTaxonName {
+    pub name: String,
+    pub locale: Option<String>,
+    pub lexicon: Option<String>,
+    pub is_valid: Option<bool>,
+}
+
+/// Observation as returned by `GET /observations`
+#[derive(Debug, Deserialize, Clone)]
+pub struct InatObservation {
+    pub id: i64,
+    /// Link to the observation on iNaturalist
+    pub uri: Option<String>,
+    pub quality_grade: Option<String>,
+    /// Local date the organism was observed, e.g. "2024-05-01"
+    pub observed_on: Option<String>,
+    pub place_guess: Option<String>,
+    /// Community identification
+    pub taxon: Option<InatTaxon>,
+    /// `url` is the square thumbnail; other sizes share its path
+    #[serde(default)]
+    pub photos: Vec<InatPhoto>,
+    pub user: Option<InatObservationUser>,
+    /// Licence of the observation data, separate from its photos'
+    pub license_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct InatObservationUser {
+    pub id: i64,
+    pub login: String,
+    pub name: Option<String>,
+}
+
+/// Place as returned by `GET /places`
+#[derive(Debug, Deserialize, Clone)]
+pub struct InatPlace {
+    pub id: i64,
+    pub name: String,
+    /// Name qualified with its country or state, e.g. "Travis County, TX, US"
+    pub display_name: Option<String>,
+    /// 0 for countries, 10 for states, 20 for counties; absent for
+    /// user-defined places
+    pub admin_level: Option<i64>,
+    /// Ids of the places containing it, largest first, including itself
+    pub ancestor_place_ids: Option<Vec<i64>>,
+    /// Centre point as "latitude,longitude"
+    pub location: Option<String>,
+    /// Area of the bounding box, in square degrees
+    pub bbox_area: Option<f64>,
+}
+
+/// One entry of a checklist from `GET /observations/species_counts`
+#[derive(Debug, Deserialize, Clone)]
+pub struct InatSpeciesCount {
+    /// Number of matching observations
+    pub count: i64,
+    pub taxon: InatTaxon,
+}
+
+/// Which observations a species checklist counts. All filters are optional.
+#[derive(Debug, Default, Clone)]
+pub struct SpeciesCountFilter {
+    /// Broad group, e.g. "Aves"
+    pub iconic_taxon: Option<String>,
+    /// The taxon or any of its descendants
+    pub taxon_id: Option<i64>,
+    /// Observed in this month of any year, 1-12
+    pub month: Option<u32>,
+}
+
+/// A page of results and how many there are in total
+#[derive(Debug)]
+pub struct Page<T> {
+    pub total: i64,
+    pub results: Vec<T>,
+}
+
+/// Which observations to pick from. All filters are optional.
+#[derive(Debug, Default, Clone)]
+pub struct ObservationFilter {
+    /// Any of these taxa or their descendants; any taxon if empty
+    pub taxon_ids: Vec<i64>,
+    pub place_id: Option<i64>,
+    /// Observed on or after
+    pub d1: Option<NaiveDate>,
+    /// Observed on or before
+    pub d2: Option<NaiveDate>,
+    /// Observed in this month of any year, 1-12
+    pub month: Option<u32>,
+    /// Only observations with a photo under one of these licences; any
+    /// licence if empty
+    pub photo_licenses: Vec<License>,
+}
+
+/// When iNaturalist was last checked, and the result
+type Reachability = (Instant, Result<(), String>);
+
+/// Client for the iNaturalist website (OAuth) and REST API.
+///
+/// Built once at startup and shared through `AppState`; cloning is cheap and
+/// every clone uses the same connection pool.
+#[derive(Clone)]
 pub struct InatClient {
     client: Client,
+    /// Website root, serving the OAuth endpoints (`INAT_BASE_URL`)
+    base_url: String,
+    /// REST API root, including the version (`INAT_API_URL`)
+    api_url: String,
+    client_id: String,
+    client_secret: Secret<String>,
+    redirect_uri: String,
+    max_retries: u32,
+    retry_base: std::time::Duration,
+    breaker: CircuitBreaker,
+    rate_limiter: RateLimiter,
+    cache: ResponseCache,
+    reachability: Arc<tokio::sync::Mutex<Option<Reachability>>>,
 }
 
 impl InatClient {
-    pub fn new() -> Self {
-        Self {
-            client: Client::new(),
+    pub fn new(cfg: &Config, redis: redis::Client) -> Result<Self> {
+        let client = Client::builder()
+            .user_agent(&cfg.inat_user_agent)
+            .connect_timeout(std::time::Duration::from_millis(
+                cfg.inat_connect_timeout_ms,
+            ))
+            .timeout(std::time::Duration::from_millis(cfg.inat_timeout_ms))
+            .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+            .build()
+            .context("failed to build iNaturalist HTTP client")?;
+
+        Ok(Self {
+            client,
+            base_url: cfg.inat_base_url.clone(),
+            api_url: cfg.inat_api_url.clone(),
+            client_id: cfg.inat_client_id.clone(),
+            client_secret: cfg.inat_client_secret.clone(),
+            redirect_uri: cfg.inat_redirect_uri.clone(),
+            max_retries: cfg.inat_max_retries,
+            retry_base: std::time::Duration::from_millis(cfg.inat_retry_base_ms),
+            breaker: CircuitBreaker::new(
+                cfg.inat_breaker_threshold,
+                std::time::Duration::from_secs(cfg.inat_breaker_cooldown_secs),
+                METRICS.inat_circuit_open.clone(),
+            ),
+            cache: ResponseCache::new(redis.clone()),
+            rate_limiter: RateLimiter::new(
+                redis,
+                cfg.inat_rate_limit_per_sec,
+                std::time::Duration::from_millis(cfg.inat_rate_limit_max_wait_ms),
+                cfg.inat_daily_limit,
+            ),
+            reachability: Arc::default(),
+        })
+    }
+
+    /// Succeeds if the iNaturalist API answers at all. Client errors still
+    /// count as reachable, since the API root itself isn't a resource. The
+    /// result is reused for [`REACHABILITY_TTL`], and concurrent checks wait
+    /// for the one in flight.
+    #[instrument(name = "inat.check_reachable", skip_all)]
+    pub async fn check_reachable(&self) -> Result<()> {
+        let mut last = self.reachability.lock().await;
+        if let Some((at, result)) = &*last
+            && at.elapsed() < REACHABILITY_TTL
+        {
+            return result.clone().map_err(|e| anyhow!(e));
         }
+
+        let result = match self
+            .send(
+                "api_root",
+                Retry::ConnectOnly,
+                Priority::Normal,
+                self.client.head(&self.api_url),
+            )
+            .await
+        {
+            Ok(_) | Err(InatError::Unauthorized(_) | InatError::Rejected(_)) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        };
+        *last = Some((Instant::now(), result.clone()));
+        result.map_err(|e| anyhow!(e))
     }
 
-    // use code from iNaturalist to get OAuth access token
-    pub async fn exchange_code_for_token(
+    /// Send a request, retrying transient failures as allowed by `retry`
+    /// with jittered exponential backoff. Non-2xx statuses become an
+    /// [`InatError`]. `endpoint` labels the call in metrics, and `priority`
+    /// picks its share of the rate limit.
+    async fn send(
+        &self,
+        endpoint: &'static str,
+        retry: Retry,
+        priority: Priority,
+        req: RequestBuilder,
+    ) -> Result<Response, InatError> {
+        let req = match request_id::current() {
+            Some(id) => req.header(REQUEST_ID_HEADER, id),
+            None => req,
+        };
+        #[cfg(feature = "otel")]
+        let req = {
+            let mut headers = reqwest::header::HeaderMap::new();
+            crate::telemetry::otel::inject_headers(&mut headers);
+            req.headers(headers)
+        };
+
+        let mut attempt = 0;
+        loop {
+            // bodies here are always buffered, so the request can be cloned
+            let this_try = req.try_clone().expect("request body is cloneable");
+            let result = self.send_once(priority, this_try).await;
+
+            let outcome = match &result {
+                Ok(_) => "success",
+                Err(e) => e.outcome(),
+            };
+            METRICS
+                .inat_requests
+                .with_label_values(&[endpoint, outcome])
+                .inc();
+
+            let err = match result {
+                Ok(resp) => return Ok(resp),
+                Err(err) => err,
+            };
+            let Some(delay) = self.retry_delay(&err, retry, attempt) else {
+                return Err(err);
+            };
+
+            attempt += 1;
+            METRICS.inat_retries.with_label_values(&[endpoint]).inc();
+            warn!(
+                endpoint,
+                attempt,
+                delay_ms = delay.as_millis() as u64,
+                "retrying iNaturalist request: {err}"
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn send_once(
+        &self,
+        priority: Priority,
+        req: RequestBuilder,
+    ) -> Result<Response, InatError> {
+        admit(&self.breaker, self.rate_limiter.acquire(priority)).await?;
+
+        let result = match req.send().await {
+            Ok(resp) if resp.status().is_success() => Ok(resp),
+            Ok(resp) => Err(InatError::from_status(
+                resp.status(),
+                parse_retry_after(&resp),
+            )),
+            Err(e) if e.is_timeout() => Err(InatError::Timeout),
+            Err(e) => Err(InatError::Network(e)),
+        };
+
+        match &result {
+            Err(e) if e.is_outage() => self.breaker.record_failure(),
+            _ => self.breaker.record_success(),
+        }
+        result
+    }
+
+    /// How long to wait before retrying after `err`, or `None` to give up
+    fn retry_delay(
         &self,
-        cfg: &Config,
-        code: &str,
-    ) -> Result<TokenWithExpiry> {
-        let url = format!("{}/oauth/token", cfg.inat_base_url);
+        err: &InatError,
+        retry: Retry,
+        attempt: u32,
+    ) -> Option<std::time::Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+        let retryable = match err {
+            InatError::Network(e) => retry == Retry::Idempotent || e.is_connect(),
+            InatError::RateLimited { .. } | InatError::Server(_) | InatError::Timeout => {
+                retry == Retry::Idempotent
+            }
+            InatError::Unauthorized(_)
+            | InatError::Rejected(_)
+            | InatError::CircuitOpen
+            | InatError::Throttled
+            | InatError::DailyBudgetExhausted => false,
+        };
+        if !retryable {
+            return None;
+        }
+
+        if let InatError::RateLimited {
+            retry_after: Some(retry_after),
+        } = err
+        {
+            return (*retry_after <= MAX_RETRY_AFTER).then_some(*retry_after);
+        }
+
+        // full jitter: anywhere between zero and the exponential ceiling
+        let ceiling = self
+            .retry_base
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(MAX_BACKOFF);
+        let millis = rand::rng().random_range(0..=ceiling.as_millis() as u64);
+        Some(std::time::Duration::from_millis(millis))
+    }
+
+    // use code from iNaturalist to get OAuth access token
+    #[instrument(name = "inat.exchange_code_for_token", skip_all)]
+    pub async fn exchange_code_for_token(&self, code: &str) -> Result<TokenWithExpiry> {
+        let url = format!("{}/oauth/token", self.base_url);
 
         let params = [
-            ("client_id", cfg.inat_client_id.as_str()),
-            ("client_secret", cfg.inat_client_secret.as_str()),
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.expose_str()),
             ("code", code),
-            ("redirect_uri", cfg.inat_redirect_uri.as_str()),
+            ("redirect_uri", self.redirect_uri.as_str()),
             ("grant_type", "authorization_code"),
         ];
 
         let resp = self
-            .client
-            .post(url)
-            .form(&params)
-            .send()
-            .await?
-            .error_for_status()?;
+            .send(
+                "oauth_token",
+                Retry::ConnectOnly,
+                Priority::Auth,
+                self.client.post(url).form(&params),
+            )
+            .await?;
 
         let body: OAuthTokenResponse = resp.json().await?;
 
         let expires_at = match (body.created_at, body.expires_in) {
             (Some(created), Some(expires_in)) => Some(
-                DateTime::<Utc>::from_timestamp(created, 0).unwrap_or_else(|| Utc::now())
+                DateTime::<Utc>::from_timestamp(created, 0).unwrap_or_else(Utc::now)
                     + Duration::seconds(expires_in),
             ),
             _ => None,
@@ -76,64 +542,513 @@ impl InatClient {
     }
 
     // use OAuth access token to get a JWT api_token
+    #[instrument(name = "inat.exchange_access_for_api_token", skip_all)]
     pub async fn exchange_access_for_api_token(
         &self,
-        cfg: &Config,
-        access_token: &str,
-    ) -> Result<String> {
-        let url = format!("{}/users/api_token", cfg.inat_base_url);
+        access_token: &Secret<String>,
+    ) -> Result<Secret<String>> {
+        let url = format!("{}/users/api_token", self.base_url);
 
         let resp = self
-            .client
-            .get(url)
-            .bearer_auth(access_token)
-            .send()
-            .await?
-            .error_for_status()?;
+            .send(
+                "users_api_token",
+                Retry::Idempotent,
+                Priority::Auth,
+                self.client.get(url).bearer_auth(access_token.expose_str()),
+            )
+            .await?;
 
         let body: ApiTokenResponse = resp.json().await?;
         Ok(body.api_token)
     }
 
     /// use JWT api_token to get user info
-    pub async fn fetch_current_user(&self, api_token: &str) -> Result<InatUser> {
-        let url = format!("{}/users/me", INAT_API_BASE);
+    #[instrument(name = "inat.fetch_current_user", skip_all)]
+    pub async fn fetch_current_user(&self, api_token: &Secret<String>) -> Result<InatUser> {
+        let url = format!("{}/users/me", self.api_url);
 
         let resp = self
-            .client
-            .get(url)
-            .bearer_auth(api_token)
-            .send()
-            .await?
-            .error_for_status()?;
-
-        let body: UsersMeResponse = resp.json().await?;
+            .send(
+                "users_me",
+                Retry::Idempotent,
+                Priority::Auth,
+                self.client.get(url).bearer_auth(api_token.expose_str()),
+            )
+            .await?;
+
+        let body: ResultsPage<InatUser> = resp.json().await?;
         let user = body
             .results
             .into_iter()
             .next()
-            .ok_or_else(|| anyhow!("empty results from /v1/users/me"))?;
+            .ok_or_else(|| anyhow!("empty results from /users/me"))?;
 
         Ok(user)
     }
+
+    /// `GET` an API path and return the body. Idempotent, so retried.
+    async fn get_text(
+        &self,
+        endpoint: &'static str,
+        path: &str,
+        query: &[(&'static str, String)],
+    ) -> Result<String> {
+        let url = format!("{}{path}", self.api_url);
+        let resp = self
+            .send(
+                endpoint,
+                Retry::Idempotent,
+                Priority::Normal,
+                self.client.get(url).query(query),
+            )
+            .await?;
+        Ok(resp.text().await?)
+    }
+
+    /// `GET` an API path through the response cache. The key is built from
+    /// the path and sorted query, so callers should normalise values that
+    /// don't change the response (e.g. letter case in search terms).
+    async fn cached_get<T: DeserializeOwned>(
+        &self,
+        endpoint: &'static str,
+        policy: CachePolicy,
+        path: String,
+        mut query: Vec<(&'static str, String)>,
+    ) -> Result<T> {
+        query.sort();
+        let key = format!(
+            "{endpoint}:{path}?{}",
+            query
+                .iter()
+                .map(|(k, v)| format!("{k}={}", urlencoding::encode(v)))
+                .collect::<Vec<_>>()
+                .join("&")
+        );
+
+        let client = self.clone();
+        let body = self
+            .cache
+            .get_or_fetch(endpoint, &key, policy, move || async move {
+                client.get_text(endpoint, &path, &query).await
+            })
+            .await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Taxa whose names match `q`, best matches first
+    #[instrument(name = "inat.autocomplete_taxa", skip(self))]
+    pub async fn autocomplete_taxa(
+        &self,
+        q: &str,
+        locale: Option<&str>,
+        per_page: u32,
+    ) -> Result<Vec<InatTaxon>> {
+        let mut query = vec![
+            ("q", q.trim().to_lowercase()),
+            ("per_page", per_page.to_string()),
+        ];
+        if let Some(locale) = locale {
+            query.push(("locale", locale.to_lowercase()));
+        }
+
+        let body: ResultsPage<InatTaxon> = self
+            .cached_get(
+                "taxa_autocomplete",
+                AUTOCOMPLETE_CACHE,
+                "/taxa/autocomplete".to_string(),
+                query,
+            )
+            .await?;
+        Ok(body.results)
+    }
+
+    /// A single taxon with its ancestors and all its names, or `None` if
+    /// there's no taxon with that id
+    #[instrument(name = "inat.fetch_taxon", skip(self))]
+    pub async fn fetch_taxon(&self, id: i64, locale: Option<&str>) -> Result<Option<InatTaxon>> {
+        let mut query = vec![("all_names", "true".to_string())];
+        if let Some(locale) = locale {
+            query.push(("locale", locale.to_lowercase()));
+        }
+
+        let result = self
+            .cached_get::<ResultsPage<InatTaxon>>(
+                "taxa_show",
+                TAXA_SHOW_CACHE,
+                format!("/taxa/{id}"),
+                query,
+            )
+            .await;
+        match result {
+            Ok(body) => Ok(body.results.into_iter().next()),
+            Err(e) if is_not_found(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Several taxa by id, with all their names, bypassing the cache. Used
+    /// for bulk refreshes of the local mirror. Unknown ids are left out.
+    #[instrument(name = "inat.fetch_taxa", skip_all, fields(count = ids.len()))]
+    pub async fn fetch_taxa(&self, ids: &[i64], locale: Option<&str>) -> Result<Vec<InatTaxon>> {
+        let ids = ids.iter().map(i64::to_string).collect::<Vec<_>>().join(",");
+        let mut query = vec![
+            ("all_names", "true".to_string()),
+            ("per_page", MAX_PER_PAGE.to_string()),
+        ];
+        if let Some(locale) = locale {
+            query.push(("locale", locale.to_lowercase()));
+        }
+
+        let body = match self
+            .get_text("taxa_show", &format!("/taxa/{ids}"), &query)
+            .await
+        {
+            Ok(body) => body,
+            Err(e) if is_not_found(&e) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let page: ResultsPage<InatTaxon> = serde_json::from_str(&body)?;
+        Ok(page.results)
+    }
+
+    /// A random page of research-grade observations with photos matching
+    /// `filter`. Never cached, so repeated calls return different
+    /// observations.
+    #[instrument(name = "inat.sample_observations", skip(self))]
+    pub async fn sample_observations(
+        &self,
+        filter: &ObservationFilter,
+        locale: Option<&str>,
+        per_page: usize,
+    ) -> Result<Vec<InatObservation>> {
+        let query = observation_query(filter, locale, per_page);
+        let body = self
+            .get_text("observations_search", "/observations", &query)
+            .await?;
+        let page: ResultsPage<InatObservation> = serde_json::from_str(&body)?;
+        Ok(page.results)
+    }
+
+    /// Places whose names match `q`, best matches first
+    #[instrument(name = "inat.autocomplete_places", skip(self))]
+    pub async fn autocomplete_places(&self, q: &str, per_page: u32) -> Result<Vec<InatPlace>> {
+        let query = vec![
+            ("q", q.trim().to_lowercase()),
+            ("per_page", per_page.to_string()),
+        ];
+
+        let body: ResultsPage<InatPlace> = self
+            .cached_get(
+                "places_autocomplete",
+                AUTOCOMPLETE_CACHE,
+                "/places/autocomplete".to_string(),
+                query,
+            )
+            .await?;
+        Ok(body.results)
+    }
+
+    /// A single place, or `None` if there's no place with that id
+    #[instrument(name = "inat.fetch_place", skip(self))]
+    pub async fn fetch_place(&self, id: i64) -> Result<Option<InatPlace>> {
+        let result = self
+            .cached_get::<ResultsPage<InatPlace>>(
+                "places_show",
+                PLACES_CACHE,
+                format!("/places/{id}"),
+                Vec::new(),
+            )
+            .await;
+        match result {
+            Ok(body) => Ok(body.results.into_iter().next()),
+            Err(e) if is_not_found(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Species with research-grade observations, in a place or anywhere,
+    /// most observed first. `page` starts at 1.
+    #[instrument(name = "inat.species_counts", skip(self))]
+    pub async fn species_counts(
+        &self,
+        place_id: Option<i64>,
+        filter: &SpeciesCountFilter,
+        locale: Option<&str>,
+        per_page: u32,
+        page: u32,
+    ) -> Result<Page<InatSpeciesCount>> {
+        let mut query = vec![
+            ("quality_grade", "research".to_string()),
+            ("per_page", per_page.to_string()),
+            ("page", page.to_string()),
+        ];
+        if let Some(place_id) = place_id {
+            query.push(("place_id", place_id.to_string()));
+        }
+        if let Some(iconic_taxon) = &filter.iconic_taxon {
+            query.push(("iconic_taxa", iconic_taxon.clone()));
+        }
+        if let Some(taxon_id) = filter.taxon_id {
+            query.push(("taxon_id", taxon_id.to_string()));
+        }
+        if let Some(month) = filter.month {
+            query.push(("month", month.to_string()));
+        }
+        if let Some(locale) = locale {
+            query.push(("locale", locale.to_lowercase()));
+        }
+
+        let body: ResultsPage<InatSpeciesCount> = self
+            .cached_get(
+                "species_counts",
+                SPECIES_COUNTS_CACHE,
+                "/observations/species_counts".to_string(),
+                query,
+            )
+            .await?;
+        Ok(Page {
+            total: body.total_results.unwrap_or(body.results.len() as i64),
+            results: body.results,
+        })
+    }
+}
+
+/// Query for [`InatClient::sample_observations`]
+fn observation_query(
+    filter: &ObservationFilter,
+    locale: Option<&str>,
+    per_page: usize,
+) -> Vec<(&'static str, String)> {
+    let mut query = vec![
+        ("quality_grade", "research".to_string()),
+        ("photos", "true".to_string()),
+        ("order_by", "random".to_string()),
+        ("per_page", per_page.min(MAX_PER_PAGE).to_string()),
+    ];
+    if !filter.taxon_ids.is_empty() {
+        let ids: Vec<_> = filter.taxon_ids.iter().map(i64::to_string).collect();
+        query.push(("taxon_id", ids.join(",")));
+    }
+    if let Some(place_id) = filter.place_id {
+        query.push(("place_id", place_id.to_string()));
+    }
+    if let Some(d1) = filter.d1 {
+        query.push(("d1", d1.to_string()));
+    }
+    if let Some(d2) = filter.d2 {
+        query.push(("d2", d2.to_string()));
+    }
+    if let Some(month) = filter.month {
+        query.push(("month", month.to_string()));
+    }
+    // iNaturalist can only filter on Creative Commons licences, and would
+    // drop photos under any other allowed one, so then the caller filters
+    if !filter.photo_licenses.is_empty()
+        && filter
+            .photo_licenses
+            .iter()
+            .all(|l| l.is_creative_commons())
+    {
+        let codes: Vec<_> = filter.photo_licenses.iter().map(|l| l.code()).collect();
+        query.push(("photo_license", codes.join(",")));
+    }
+    if let Some(locale) = locale {
+        query.push(("locale", locale.to_lowercase()));
+    }
+    query
+}
+
+/// Wait for a rate limit `slot`, then check the circuit breaker. The trial
+/// call after a cooldown is only claimed once the request can actually be
+/// sent, so a throttled one doesn't leave the circuit half-open with nothing
+/// to report back. While the circuit is open, no slot is spent at all.
+async fn admit(
+    breaker: &CircuitBreaker,
+    slot: impl Future<Output = Result<(), Throttled>>,
+) -> Result<(), InatError> {
+    if breaker.is_open() {
+        return Err(InatError::CircuitOpen);
+    }
+    slot.await.map_err(|t| match t {
+        Throttled::QueueFull => InatError::Throttled,
+        Throttled::DailyBudget => InatError::DailyBudgetExhausted,
+    })?;
+    if !breaker.allow() {
+        return Err(InatError::CircuitOpen);
+    }
+    Ok(())
+}
+
+fn is_not_found(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<InatError>(),
+        Some(InatError::Rejected(StatusCode::NOT_FOUND))
+    )
+}
+
+/// Parses `Retry-After` as either delay seconds or an HTTP date
+fn parse_retry_after(resp: &Response) -> Option<std::time::Duration> {
+    let value = resp.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(std::time::Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value)
+        .ok()?
+        .with_timezone(&Utc);
+    // a date in the past means "now"
+    Some((at - Utc::now()).to_std().unwrap_or_default())
 }
 
 #[derive(Debug, Deserialize)]
 struct ApiTokenResponse {
-    api_token: String,
+    api_token: Secret<String>,
 }
 
+/// Envelope of most iNaturalist API responses
 #[derive(Debug, Deserialize)]
-struct UsersMeResponse {
-    results: Vec<InatUser>,
+struct ResultsPage<T> {
+    /// Absent on endpoints that don't paginate
+    total_results: Option<i64>,
+    results: Vec<T>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct OAuthTokenResponse {
-    pub access_token: String,
+    pub access_token: Secret<String>,
     pub token_type: String,
     pub scope: Option<String>,
-    pub refresh_token: Option<String>,
+    pub refresh_token: Option<Secret<String>>,
     pub expires_in: Option<i64>, // seconds
     pub created_at: Option<i64>, // unix timestamp (seconds)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::future::ready;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use prometheus::IntGauge;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// A client for a fake iNaturalist that answers every request with
+    /// `status`, and the number of requests it has received
+    async fn fake_inat(status: &'static str) -> (InatClient, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = Config::for_tests();
+        config.inat_api_url = format!("http://{}", listener.local_addr().unwrap());
+        config.inat_max_retries = 0;
+        // nothing listens here, so the rate limiter lets calls through
+        let redis = redis::Client::open("redis://127.0.0.1:1").unwrap();
+        let inat = InatClient::new(&config, redis).unwrap();
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut head = Vec::new();
+                let mut buf = [0; 1024];
+                while !head.ends_with(b"\r\n\r\n") {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    head.extend_from_slice(&buf[..n]);
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+                let response =
+                    format!("HTTP/1.1 {status}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n");
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (inat, requests)
+    }
+
+    fn photo_license_param(licenses: &[License]) -> Option<String> {
+        let filter = ObservationFilter {
+            photo_licenses: licenses.to_vec(),
+            ..Default::default()
+        };
+        observation_query(&filter, None, MAX_PER_PAGE)
+            .into_iter()
+            .find(|(key, _)| *key == "photo_license")
+            .map(|(_, value)| value)
+    }
+
+    #[test]
+    fn creative_commons_licences_are_filtered_upstream() {
+        assert_eq!(
+            photo_license_param(&[License::CcBy, License::Cc0]).as_deref(),
+            Some("cc-by,cc0")
+        );
+        assert_eq!(photo_license_param(&[]), None);
+    }
+
+    #[test]
+    fn other_licences_are_left_to_the_caller() {
+        // iNaturalist would drop the public domain and GFDL photos
+        assert_eq!(photo_license_param(crate::license::REUSABLE), None);
+        assert_eq!(photo_license_param(&[License::PublicDomain]), None);
+        assert_eq!(
+            photo_license_param(&[License::CcBy, License::AllRightsReserved]),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn reachability_checks_are_reused() {
+        let (inat, requests) = fake_inat("404 Not Found").await;
+
+        inat.check_reachable().await.unwrap();
+        inat.check_reachable().await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_unreachable() {
+        let (inat, requests) = fake_inat("503 Service Unavailable").await;
+
+        assert!(inat.check_reachable().await.is_err());
+        assert!(inat.check_reachable().await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    const COOLDOWN: std::time::Duration = std::time::Duration::from_millis(20);
+
+    /// A breaker whose cooldown has just run out, ready for a trial call
+    async fn cooled_down_breaker() -> CircuitBreaker {
+        let breaker = CircuitBreaker::new(1, COOLDOWN, IntGauge::new("test", "test").unwrap());
+        breaker.record_failure();
+        assert!(breaker.is_open());
+        tokio::time::sleep(COOLDOWN).await;
+        assert!(!breaker.is_open());
+        breaker
+    }
+
+    #[tokio::test]
+    async fn throttled_probes_leave_the_trial_call_unclaimed() {
+        let breaker = cooled_down_breaker().await;
+
+        let throttled = admit(&breaker, ready(Err(Throttled::QueueFull))).await;
+        assert!(matches!(throttled, Err(InatError::Throttled)));
+        let exhausted = admit(&breaker, ready(Err(Throttled::DailyBudget))).await;
+        assert!(matches!(exhausted, Err(InatError::DailyBudgetExhausted)));
+
+        admit(&breaker, ready(Ok(()))).await.unwrap();
+        // the trial is now in flight
+        let blocked = admit(&breaker, ready(Ok(()))).await;
+        assert!(matches!(blocked, Err(InatError::CircuitOpen)));
+        breaker.record_success();
+        admit(&breaker, ready(Ok(()))).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn open_circuits_spend_no_rate_limit_slot() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN, IntGauge::new("test", "test").unwrap());
+        breaker.record_failure();
+
+        let slot = async { panic!("rate limit slot taken while the circuit is open") };
+        let refused = admit(&breaker, slot).await;
+        assert!(matches!(refused, Err(InatError::CircuitOpen)));
+    }
+}
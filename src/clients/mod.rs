@@ -1 +1,4 @@
+pub mod circuit_breaker;
 pub mod inat;
+pub mod rate_limiter;
+pub mod response_cache;
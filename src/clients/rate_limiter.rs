@@ -0,0 +1,172 @@
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use chrono::Utc;
+use redis::Script;
+use tracing::{instrument, warn};
+
+use crate::metrics::METRICS;
+
+/// Reserves the next free send slot and counts it against the daily budget.
+/// Returns the milliseconds to wait before sending, -1 if that would exceed
+/// the maximum wait, or -2 if the daily budget is used up.
+///
+/// Slots are handed out in order, `interval` apart, from a timestamp shared
+/// by every replica, so callers queue behind each other instead of racing.
+/// The budget (`ARGV[3]`) is passed per call, so callers can be held to a
+/// smaller share of the same count, and `ARGV[4]` = 1 joins the queue
+/// however long it is.
+static RESERVE: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+local t = redis.call('TIME')
+local now = tonumber(t[1]) * 1000 + math.floor(tonumber(t[2]) / 1000)
+local interval = tonumber(ARGV[1])
+
+local used = tonumber(redis.call('GET', KEYS[2]) or '0')
+if used >= tonumber(ARGV[3]) then
+    return -2
+end
+
+local next_slot = tonumber(redis.call('GET', KEYS[1]) or '0')
+if next_slot < now then
+    next_slot = now
+end
+local wait = next_slot - now
+if wait > tonumber(ARGV[2]) and ARGV[4] ~= '1' then
+    return -1
+end
+
+redis.call('SET', KEYS[1], next_slot + interval, 'PX', wait + interval + 1000)
+redis.call('INCR', KEYS[2])
+redis.call('EXPIRE', KEYS[2], 172800)
+return wait
+",
+    )
+});
+
+/// Share of the daily budget, in percent, only [`Priority::Auth`] calls may
+/// use, so other traffic can't lock users out
+const AUTH_RESERVE_PERCENT: u32 = 10;
+
+/// Which share of the budget a call draws from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Everything that isn't signing in
+    Normal,
+    /// Signing in. May use the whole daily budget, and waits in the queue
+    /// instead of being turned away when it's full. Normal calls keep the
+    /// queue within the maximum wait, so it's never much longer.
+    Auth,
+}
+
+/// Why a call wasn't allowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Throttled {
+    /// The queue is longer than the maximum wait
+    QueueFull,
+    /// The daily request budget is used up
+    DailyBudget,
+}
+
+/// Rate limit shared by all replicas through Redis, so together they stay
+/// within iNaturalist's API etiquette (about 1 request/second, 10k/day).
+///
+/// If Redis is unavailable, calls are let through rather than failing
+/// logins over a missing rate limiter.
+#[derive(Clone)]
+pub struct RateLimiter {
+    redis: redis::Client,
+    interval: Duration,
+    max_wait: Duration,
+    daily_limit: u32,
+}
+
+impl RateLimiter {
+    pub fn new(
+        redis: redis::Client,
+        per_second: u32,
+        max_wait: Duration,
+        daily_limit: u32,
+    ) -> Self {
+        Self {
+            redis,
+            interval: Duration::from_secs(1) / per_second,
+            max_wait,
+            daily_limit,
+        }
+    }
+
+    /// Wait for this replica's turn to send a request
+    #[instrument(name = "inat.rate_limit", skip(self))]
+    pub async fn acquire(&self, priority: Priority) -> Result<(), Throttled> {
+        let wait = match self.reserve(priority).await {
+            Ok(wait) => wait,
+            Err(e) => {
+                warn!("iNaturalist rate limiter unavailable, not limiting: {e}");
+                return Ok(());
+            }
+        };
+
+        let wait = match wait {
+            -1 => {
+                METRICS
+                    .inat_throttled
+                    .with_label_values(&["queue_full"])
+                    .inc();
+                return Err(Throttled::QueueFull);
+            }
+            -2 => {
+                METRICS
+                    .inat_throttled
+                    .with_label_values(&["daily_budget"])
+                    .inc();
+                return Err(Throttled::DailyBudget);
+            }
+            wait => Duration::from_millis(wait.max(0) as u64),
+        };
+
+        METRICS.inat_rate_limit_wait.observe(wait.as_secs_f64());
+        if !wait.is_zero() {
+            METRICS.inat_throttled.with_label_values(&["delayed"]).inc();
+            tokio::time::sleep(wait).await;
+        }
+        Ok(())
+    }
+
+    async fn reserve(&self, priority: Priority) -> redis::RedisResult<i64> {
+        let _timer = METRICS.redis_timer("inat_rate_limit");
+        let mut conn = self.redis.get_multiplexed_async_connection().await?;
+        let day_key = format!("inat_rate:day:{}", Utc::now().format("%Y-%m-%d"));
+        RESERVE
+            .key("inat_rate:next_slot")
+            .key(day_key)
+            .arg(self.interval.as_millis() as u64)
+            .arg(self.max_wait.as_millis() as u64)
+            .arg(budget(self.daily_limit, priority))
+            .arg(u8::from(priority == Priority::Auth))
+            .invoke_async(&mut conn)
+            .await
+    }
+}
+
+/// The part of `daily_limit` calls of this priority may use
+fn budget(daily_limit: u32, priority: Priority) -> u32 {
+    match priority {
+        Priority::Normal => daily_limit - daily_limit * AUTH_RESERVE_PERCENT / 100,
+        Priority::Auth => daily_limit,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auth_calls_keep_a_share_of_the_budget() {
+        assert_eq!(budget(10_000, Priority::Normal), 9_000);
+        assert_eq!(budget(10_000, Priority::Auth), 10_000);
+        // tiny budgets aren't reserved away entirely
+        assert_eq!(budget(5, Priority::Normal), 5);
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::Utc;
+use redis::AsyncCommands;
+use tracing::{instrument, warn};
+
+use crate::metrics::METRICS;
+
+/// How long a cached response is served as-is, and for how long after that
+/// it's still served while being refreshed in the background.
+#[derive(Debug, Clone, Copy)]
+pub struct CachePolicy {
+    pub fresh: Duration,
+    pub stale: Duration,
+}
+
+/// Caches upstream response bodies in Redis.
+///
+/// Concurrent misses for the same key within this process wait for a single
+/// fetch. Stale entries are refreshed by one replica at a time, guarded by a
+/// short Redis lock. If Redis is unavailable, every call goes upstream.
+#[derive(Clone)]
+pub struct ResponseCache {
+    redis: redis::Client,
+    /// One lock per key currently being fetched
+    inflight: Arc<Mutex<Inflight>>,
+}
+
+type Inflight = HashMap<String, Arc<tokio::sync::Mutex<()>>>;
+
+/// A share of a key's inflight lock. The last one dropped removes the lock
+/// from the map, including when the caller is cancelled mid-fetch.
+struct InflightEntry<'a> {
+    inflight: &'a Mutex<Inflight>,
+    key: &'a str,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<'a> InflightEntry<'a> {
+    fn join(inflight: &'a Mutex<Inflight>, key: &'a str) -> Self {
+        let lock = inflight
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+        Self {
+            inflight,
+            key,
+            lock,
+        }
+    }
+}
+
+impl Drop for InflightEntry<'_> {
+    fn drop(&mut self) {
+        let mut inflight = self.inflight.lock().unwrap_or_else(|e| e.into_inner());
+        // shares are only taken with the map locked, so nobody else can join
+        // between this check and the removal
+        let last = inflight
+            .get(self.key)
+            .is_some_and(|l| Arc::ptr_eq(l, &self.lock) && Arc::strong_count(l) == 2);
+        if last {
+            inflight.remove(self.key);
+        }
+    }
+}
+
+/// A cached body and its age
+struct Entry {
+    body: String,
+    age: Duration,
+}
+
+/// Held while one replica refreshes a stale entry
+const REFRESH_LOCK_TTL: Duration = Duration::from_secs(30);
+
+impl ResponseCache {
+    pub fn new(redis: redis::Client) -> Self {
+        Self {
+            redis,
+            inflight: Arc::default(),
+        }
+    }
+
+    fn entry_key(key: &str) -> String {
+        format!("inat_cache:{key}")
+    }
+
+    fn refresh_lock_key(key: &str) -> String {
+        format!("inat_cache_refresh:{key}")
+    }
+
+    /// Returns the cached body for `key`, calling `fetch` on a miss. `endpoint`
+    /// labels the lookup in metrics.
+    #[instrument(name = "inat.cache", skip(self, policy, fetch))]
+    pub async fn get_or_fetch<F, Fut>(
+        &self,
+        endpoint: &'static str,
+        key: &str,
+        policy: CachePolicy,
+        fetch: F,
+    ) -> Result<String>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        match self.read(key).await {
+            Ok(Some(entry)) if entry.age < policy.fresh => {
+                record(endpoint, "hit");
+                return Ok(entry.body);
+            }
+            Ok(Some(entry)) if entry.age < policy.fresh + policy.stale => {
+                record(endpoint, "stale");
+                self.refresh_in_background(endpoint, key, policy, fetch)
+                    .await;
+                return Ok(entry.body);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!(endpoint, "response cache unavailable: {e}");
+                record(endpoint, "error");
+                return fetch().await;
+            }
+        }
+
+        let entry = InflightEntry::join(&self.inflight, key);
+        let _guard = entry.lock.lock().await;
+
+        // another request may have filled the cache while we waited
+        if let Ok(Some(entry)) = self.read(key).await
+            && entry.age < policy.fresh
+        {
+            record(endpoint, "coalesced");
+            return Ok(entry.body);
+        }
+
+        record(endpoint, "miss");
+        let result = fetch().await;
+        if let Ok(body) = &result
+            && let Err(e) = self.write(key, body, policy).await
+        {
+            warn!(endpoint, "failed to write response cache: {e}");
+        }
+        result
+    }
+
+    async fn refresh_in_background<F, Fut>(
+        &self,
+        endpoint: &'static str,
+        key: &str,
+        policy: CachePolicy,
+        fetch: F,
+    ) where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        match self.try_refresh_lock(key).await {
+            Ok(true) => {}
+            // someone else is already refreshing it
+            Ok(false) => return,
+            Err(e) => {
+                warn!(endpoint, "response cache unavailable: {e}");
+                return;
+            }
+        }
+
+        let cache = self.clone();
+        let key = key.to_string();
+        tokio::spawn(async move {
+            let result = match fetch().await {
+                Ok(body) => cache.write(&key, &body, policy).await.map_err(Into::into),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                warn!(endpoint, "failed to refresh cached response: {e:#}");
+            }
+        });
+    }
+
+    async fn read(&self, key: &str) -> redis::RedisResult<Option<Entry>> {
+        let _timer = METRICS.redis_timer("response_cache_read");
+        let mut conn = self.redis.get_multiplexed_async_connection().await?;
+        let (fetched_at, body): (Option<i64>, Option<String>) = conn
+            .hget(Self::entry_key(key), &["fetched_at", "body"])
+            .await?;
+        let (Some(fetched_at), Some(body)) = (fetched_at, body) else {
+            return Ok(None);
+        };
+        let age = (Utc::now().timestamp() - fetched_at).max(0) as u64;
+        Ok(Some(Entry {
+            body,
+            age: Duration::from_secs(age),
+        }))
+    }
+
+    async fn write(&self, key: &str, body: &str, policy: CachePolicy) -> redis::RedisResult<()> {
+        let _timer = METRICS.redis_timer("response_cache_write");
+        let mut conn = self.redis.get_multiplexed_async_connection().await?;
+        let entry_key = Self::entry_key(key);
+        redis::pipe()
+            .atomic()
+            .hset_multiple(
+                &entry_key,
+                &[
+                    ("fetched_at", Utc::now().timestamp().to_string()),
+                    ("body", body.to_string()),
+                ],
+            )
+            .ignore()
+            .expire(&entry_key, (policy.fresh + policy.stale).as_secs() as i64)
+            .ignore()
+            .del(Self::refresh_lock_key(key))
+            .ignore()
+            .query_async(&mut conn)
+            .await
+    }
+
+    async fn try_refresh_lock(&self, key: &str) -> redis::RedisResult<bool> {
+        let mut conn = self.redis.get_multiplexed_async_connection().await?;
+        let acquired: Option<String> = redis::cmd("SET")
+            .arg(Self::refresh_lock_key(key))
+            .arg(1)
+            .arg("NX")
+            .arg("PX")
+            .arg(REFRESH_LOCK_TTL.as_millis() as u64)
+            .query_async(&mut conn)
+            .await?;
+        Ok(acquired.is_some())
+    }
+}
+
+fn record(endpoint: &str, result: &str) {
+    METRICS
+        .inat_cache_requests
+        .with_label_values(&[endpoint, result])
+        .inc();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::{Inflight, InflightEntry};
+
+    #[test]
+    fn last_inflight_entry_removes_the_lock() {
+        let inflight = Mutex::new(Inflight::new());
+
+        let first = InflightEntry::join(&inflight, "taxa/3");
+        let second = InflightEntry::join(&inflight, "taxa/3");
+        drop(first);
+        assert!(inflight.lock().unwrap().contains_key("taxa/3"));
+
+        drop(second);
+        assert!(inflight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cancelled_fetch_removes_the_lock() {
+        let inflight = Mutex::new(Inflight::new());
+
+        let fetch = async {
+            let entry = InflightEntry::join(&inflight, "taxa/3");
+            let _guard = entry.lock.lock().await;
+            std::future::pending::<()>().await;
+        };
+        // dropped while still waiting on the upstream response
+        let timed_out = tokio::time::timeout(std::time::Duration::from_millis(10), fetch).await;
+
+        assert!(timed_out.is_err());
+        assert!(inflight.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+use poem::http::{HeaderMap, HeaderValue, Method, StatusCode, header};
+use poem::web::{Compress, CompressionAlgo};
+use poem::{Body, Endpoint, IntoResponse, Middleware, Request, Response, Result};
+
+/// Bodies smaller than this aren't worth the CPU or the encoding overhead
+const MIN_SIZE: usize = 1024;
+
+/// Compresses text and JSON responses with brotli or gzip, whichever the
+/// client prefers.
+///
+/// Unlike poem's `Compression`, this leaves small, empty and already-encoded
+/// bodies alone and sends `Vary: Accept-Encoding` so caches keep the
+/// encodings apart. Request bodies are not decompressed.
+pub struct Compression;
+
+impl<E: Endpoint> Middleware<E> for Compression {
+    type Output = CompressionEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        CompressionEndpoint { inner: ep }
+    }
+}
+
+pub struct CompressionEndpoint<E> {
+    inner: E,
+}
+
+impl<E: Endpoint> Endpoint for CompressionEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let algo = negotiate(req.headers());
+        let is_head = req.method() == Method::HEAD;
+
+        let mut resp = self.inner.call(req).await?.into_response();
+        if !is_compressible(&resp) {
+            return Ok(resp);
+        }
+        resp.headers_mut()
+            .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+
+        let Some(algo) = algo else {
+            return Ok(resp);
+        };
+        if is_head
+            || matches!(
+                resp.status(),
+                StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED
+            )
+        {
+            return Ok(resp);
+        }
+
+        let body = resp.take_body().into_bytes().await?;
+        if body.len() < MIN_SIZE {
+            resp.set_body(Body::from_bytes(body));
+            return Ok(resp);
+        }
+
+        // the encoded bytes differ from what a strong ETag was computed over
+        if let Some(etag) = resp.headers().get(header::ETAG)
+            && !etag.as_bytes().starts_with(b"W/")
+        {
+            let weak = [b"W/", etag.as_bytes()].concat();
+            if let Ok(weak) = HeaderValue::from_bytes(&weak) {
+                resp.headers_mut().insert(header::ETAG, weak);
+            }
+        }
+
+        resp.set_body(Body::from_bytes(body));
+        Ok(Compress::new(resp, algo).into_response())
+    }
+}
+
+fn is_compressible(resp: &Response) -> bool {
+    if resp.headers().contains_key(header::CONTENT_ENCODING) {
+        return false;
+    }
+    let Some(content_type) = resp.content_type() else {
+        return false;
+    };
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    mime.starts_with("text/")
+        || mime.ends_with("json")
+        || mime.ends_with("+xml")
+        || mime == "application/javascript"
+        || mime == "image/svg+xml"
+}
+
+/// Picks brotli or gzip from `Accept-Encoding`, by q-value and then by
+/// preference for brotli. `None` means the body is sent as-is.
+fn negotiate(headers: &HeaderMap) -> Option<CompressionAlgo> {
+    let mut best: Option<(CompressionAlgo, f32)> = None;
+
+    for value in headers.get_all(header::ACCEPT_ENCODING) {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+        for item in value.split(',') {
+            let mut parts = item.split(';').map(str::trim);
+            let coding = parts.next().unwrap_or_default().to_ascii_lowercase();
+            let q = parts
+                .find_map(|p| p.strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            let algo = match coding.as_str() {
+                "br" => CompressionAlgo::BR,
+                "gzip" | "x-gzip" => CompressionAlgo::GZIP,
+                _ => continue,
+            };
+            if q <= 0.0 {
+                continue;
+            }
+            let better = match best {
+                None => true,
+                Some((_, best_q)) if q > best_q => true,
+                Some((_, best_q)) => q == best_q && algo == CompressionAlgo::BR,
+            };
+            if better {
+                best = Some((algo, q));
+            }
+        }
+    }
+
+    best.map(|(algo, _)| algo)
+}
@@ -1,21 +1,89 @@
 use std::env::var;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use url::Url;
+
+use crate::secret::Secret;
+
+/// Default location of the optional config file, overridden by `CONFIG_FILE`
+const DEFAULT_CONFIG_FILE: &str = "taxonia.toml";
+
+const DEFAULT_USER_AGENT: &str = concat!("taxonia_api/", env!("CARGO_PKG_VERSION"));
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub allowed_origins: Vec<String>,
-    pub database_url: String,
-    pub redis_url: String,
+    pub database_url: Secret<String>,
+    pub redis_url: Secret<String>,
     pub bind_addr: String,
     pub base_url: String,
 
     pub inat_client_id: String,
-    pub inat_client_secret: String,
+    pub inat_client_secret: Secret<String>,
     pub inat_redirect_uri: String,
     pub inat_base_url: String,
     pub app_redirect_uri: String,
 
     // optional vars
     pub app_env: AppEnv,
+    /// Apply pending migrations when the server starts (default: true)
+    pub run_migrations: bool,
+    /// Maximum number of Postgres connections in the pool
+    pub db_max_connections: u32,
+    /// Seconds to wait for a free Postgres connection before failing
+    pub db_acquire_timeout_secs: u64,
+    /// Lifetime of a login session in seconds
+    pub session_ttl_secs: u64,
+    /// Seconds a user has to complete the iNaturalist OAuth flow
+    pub oauth_state_ttl_secs: u64,
+    /// iNaturalist REST API root, including the version
+    pub inat_api_url: String,
+    /// `User-Agent` sent with every iNaturalist request
+    pub inat_user_agent: String,
+    /// Timeouts for iNaturalist requests, in milliseconds. The request
+    /// timeout covers the whole exchange, including reading the body.
+    pub inat_connect_timeout_ms: u64,
+    pub inat_timeout_ms: u64,
+    /// Retries after a failed iNaturalist call, on top of the first attempt
+    pub inat_max_retries: u32,
+    /// Starting delay for the exponential retry backoff, in milliseconds
+    pub inat_retry_base_ms: u64,
+    /// Consecutive failed iNaturalist calls that open the circuit breaker,
+    /// and how long it then fails fast before trying again
+    pub inat_breaker_threshold: u32,
+    pub inat_breaker_cooldown_secs: u64,
+    /// Request budget shared by all replicas, coordinated through Redis
+    pub inat_rate_limit_per_sec: u32,
+    pub inat_daily_limit: u32,
+    /// Longest a call waits in the rate limiter queue before being refused
+    pub inat_rate_limit_max_wait_ms: u64,
+    /// Seconds to keep accepting requests after a shutdown signal while the
+    /// health check reports "draining"
+    pub shutdown_delay_secs: u64,
+    /// Seconds to wait for in-flight requests before forcing shutdown
+    pub shutdown_drain_timeout_secs: u64,
+    /// Readiness check timeouts per dependency, in milliseconds
+    pub health_db_timeout_ms: u64,
+    pub health_redis_timeout_ms: u64,
+    pub health_inat_timeout_ms: u64,
+    /// Include iNaturalist reachability in the readiness check (default: false)
+    pub health_check_inat: bool,
+    /// Address of the admin listener serving `/metrics`. Metrics are not
+    /// served when unset; the port should not be exposed publicly.
+    pub metrics_bind_addr: Option<String>,
+    /// OTLP/HTTP traces endpoint, e.g. `http://localhost:4318/v1/traces`.
+    /// Export is off when unset, and needs the `otel` cargo feature.
+    pub otel_endpoint: Option<String>,
+    pub otel_service_name: String,
+    /// Fraction of new traces to sample, from 0.0 to 1.0
+    pub otel_sample_ratio: f64,
+    /// Date after which the unversioned (pre-`/v1`) routes will be removed,
+    /// advertised in the `Sunset` header
+    pub legacy_api_sunset: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -27,31 +95,127 @@ pub enum AppEnv {
 #[derive(Debug, Clone)]
 pub enum ConfigError {
     InvalidAppEnv(String),
+    InvalidBool(String),
+    File(String),
+    /// Every problem found while loading, one message per setting
+    Invalid(Vec<String>),
 }
 
 impl Config {
-    pub fn from_env() -> anyhow::Result<Self> {
-        Ok(Self {
-            allowed_origins: std::env::var("ALLOWED_ORIGINS")?
+    /// Load the config from, in increasing order of precedence: built-in
+    /// defaults, the TOML file named by `CONFIG_FILE` (`taxonia.toml` if
+    /// present), and environment variables. Keys in the file are the
+    /// lowercase forms of the environment variable names.
+    pub fn load() -> Result<Self, ConfigError> {
+        let file = match var("CONFIG_FILE") {
+            Ok(path) => Some(read_config_file(Path::new(&path))?),
+            Err(_) => {
+                let path = PathBuf::from(DEFAULT_CONFIG_FILE);
+                if path.exists() {
+                    Some(read_config_file(&path)?)
+                } else {
+                    None
+                }
+            }
+        };
+
+        Self::from_loader(Loader {
+            file: file.unwrap_or_default(),
+            errors: Vec::new(),
+        })
+    }
+
+    /// Defaults plus placeholder values for the required settings, for tests
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        let file = toml::toml! {
+            database_url = "postgres://localhost/taxonia_test"
+            inat_client_id = "test"
+            inat_client_secret = "test"
+            inat_redirect_uri = "http://localhost:8080/auth/callback"
+            app_redirect_uri = "http://localhost:5173"
+        };
+        Self::from_loader(Loader {
+            file,
+            errors: Vec::new(),
+        })
+        .expect("test config is valid")
+    }
+
+    fn from_loader(mut l: Loader) -> Result<Self, ConfigError> {
+        let config = Self {
+            allowed_origins: l
+                .string_or("allowed_origins", "")
                 .split(',')
                 .map(|s| s.trim().to_string())
                 .filter(|s| !s.is_empty())
                 .collect::<Vec<_>>(),
-            database_url: var("DATABASE_URL")?,
-            redis_url: var("REDIS_URL")?,
-            bind_addr: var("BIND_ADDR")?,
-            base_url: var("BASE_URL")?,
-
-            inat_client_id: var("INAT_CLIENT_ID")?,
-            inat_client_secret: var("INAT_CLIENT_SECRET")?,
-            inat_redirect_uri: var("INAT_REDIRECT_URI")?,
-            inat_base_url: var("INAT_BASE_URL")?,
-            app_redirect_uri: var("APP_REDIRECT_URI")?,
-
-            app_env: var("APP_ENV")
-                .map(AppEnv::try_from)
-                .unwrap_or(Ok(AppEnv::Development))?,
-        })
+            database_url: Secret::new(l.url("database_url", None, &["postgres", "postgresql"])),
+            redis_url: Secret::new(l.url(
+                "redis_url",
+                Some("redis://127.0.0.1:6379"),
+                &["redis", "rediss"],
+            )),
+            bind_addr: l.bind_addr("bind_addr", "127.0.0.1:8080"),
+            base_url: l.url("base_url", Some("http://localhost:8080"), HTTP),
+
+            inat_client_id: l.required("inat_client_id"),
+            inat_client_secret: Secret::new(l.required("inat_client_secret")),
+            inat_redirect_uri: l.url("inat_redirect_uri", None, HTTP),
+            inat_base_url: l.url("inat_base_url", Some("https://www.inaturalist.org"), HTTP),
+            app_redirect_uri: l.url("app_redirect_uri", None, HTTP),
+
+            app_env: l.parse_with("app_env", AppEnv::Development, |v| {
+                AppEnv::try_from(v.to_string())
+            }),
+            run_migrations: l
+                .parse_with("run_migrations", true, |v| parse_bool("RUN_MIGRATIONS", v)),
+            db_max_connections: l.positive("db_max_connections", 5),
+            db_acquire_timeout_secs: l.positive("db_acquire_timeout_secs", 5),
+            session_ttl_secs: l.positive("session_ttl_secs", 60 * 60 * 24 * 7),
+            oauth_state_ttl_secs: l.positive("oauth_state_ttl_secs", 600),
+            inat_api_url: l.url("inat_api_url", Some("https://api.inaturalist.org/v1"), HTTP),
+            inat_user_agent: l.string_or("inat_user_agent", DEFAULT_USER_AGENT),
+            inat_connect_timeout_ms: l.positive("inat_connect_timeout_ms", 3000),
+            inat_timeout_ms: l.positive("inat_timeout_ms", 10_000),
+            inat_max_retries: l.parse_with("inat_max_retries", 2, |v| {
+                v.parse::<u32>()
+                    .map_err(|e| format!("Invalid INAT_MAX_RETRIES: {e}"))
+            }),
+            inat_retry_base_ms: l.positive("inat_retry_base_ms", 200),
+            inat_breaker_threshold: l.positive("inat_breaker_threshold", 5),
+            inat_breaker_cooldown_secs: l.positive("inat_breaker_cooldown_secs", 30),
+            inat_rate_limit_per_sec: l.positive("inat_rate_limit_per_sec", 1),
+            inat_daily_limit: l.positive("inat_daily_limit", 10_000),
+            inat_rate_limit_max_wait_ms: l.positive("inat_rate_limit_max_wait_ms", 5000),
+            shutdown_delay_secs: l.parse_with("shutdown_delay_secs", 0, |v| {
+                v.parse::<u64>()
+                    .map_err(|e| format!("Invalid SHUTDOWN_DELAY_SECS: {e}"))
+            }),
+            shutdown_drain_timeout_secs: l.positive("shutdown_drain_timeout_secs", 30),
+            health_db_timeout_ms: l.positive("health_db_timeout_ms", 1000),
+            health_redis_timeout_ms: l.positive("health_redis_timeout_ms", 500),
+            health_inat_timeout_ms: l.positive("health_inat_timeout_ms", 2000),
+            health_check_inat: l.parse_with("health_check_inat", false, |v| {
+                parse_bool("HEALTH_CHECK_INAT", v)
+            }),
+            metrics_bind_addr: l.optional_bind_addr("metrics_bind_addr"),
+            otel_endpoint: l.optional_url("otel_endpoint", HTTP),
+            otel_service_name: l.string_or("otel_service_name", "taxonia_api"),
+            otel_sample_ratio: l.parse_with("otel_sample_ratio", 1.0, |v| match v.parse::<f64>() {
+                Ok(r) if (0.0..=1.0).contains(&r) => Ok(r),
+                _ => Err(format!(
+                    "Invalid OTEL_SAMPLE_RATIO: Received {v}. Expected a number from 0.0 to 1.0"
+                )),
+            }),
+            legacy_api_sunset: l.optional_date("legacy_api_sunset"),
+        };
+
+        if l.errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError::Invalid(l.errors))
+        }
     }
 
     pub fn is_prod(&self) -> bool {
@@ -59,6 +223,182 @@ impl Config {
     }
 }
 
+const HTTP: &[&str] = &["http", "https"];
+
+fn read_config_file(path: &Path) -> Result<toml::Table, ConfigError> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| ConfigError::File(format!("Failed to read {}: {e}", path.display())))?;
+    contents
+        .parse::<toml::Table>()
+        .map_err(|e| ConfigError::File(format!("Failed to parse {}: {e}", path.display())))
+}
+
+/// Resolves settings across the config layers and collects validation errors
+/// so they can all be reported at once.
+struct Loader {
+    file: toml::Table,
+    errors: Vec<String>,
+}
+
+impl Loader {
+    fn env_name(key: &str) -> String {
+        key.to_ascii_uppercase()
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        if let Ok(value) = var(Self::env_name(key)) {
+            return Some(value);
+        }
+        self.file.get(key).map(|value| match value {
+            toml::Value::String(s) => s.clone(),
+            toml::Value::Array(items) => items
+                .iter()
+                .map(|item| match item {
+                    toml::Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(","),
+            other => other.to_string(),
+        })
+    }
+
+    fn required(&mut self, key: &str) -> String {
+        match self.get(key) {
+            Some(value) if !value.trim().is_empty() => value,
+            _ => {
+                self.errors
+                    .push(format!("{} is required but not set", Self::env_name(key)));
+                String::new()
+            }
+        }
+    }
+
+    fn string_or(&self, key: &str, default: &str) -> String {
+        self.get(key).unwrap_or_else(|| default.to_string())
+    }
+
+    fn parse_with<T, E: Display>(
+        &mut self,
+        key: &str,
+        default: T,
+        parse: impl FnOnce(&str) -> Result<T, E>,
+    ) -> T {
+        let Some(value) = self.get(key) else {
+            return default;
+        };
+        match parse(value.trim()) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                self.errors.push(e.to_string());
+                default
+            }
+        }
+    }
+
+    fn positive<T>(&mut self, key: &str, default: T) -> T
+    where
+        T: FromStr + PartialOrd + Default + Copy,
+        T::Err: Display,
+    {
+        let name = Self::env_name(key);
+        self.parse_with(key, default, |v| match v.parse::<T>() {
+            Ok(n) if n > T::default() => Ok(n),
+            Ok(_) => Err(format!("Invalid {name}: must be greater than 0")),
+            Err(e) => Err(format!("Invalid {name}: {e}")),
+        })
+    }
+
+    fn url(&mut self, key: &str, default: Option<&str>, schemes: &[&str]) -> String {
+        let value = match default {
+            Some(default) => self.string_or(key, default),
+            None => self.required(key),
+        };
+        if value.is_empty() {
+            return value;
+        }
+        self.check_url(key, value, schemes)
+    }
+
+    fn optional_url(&mut self, key: &str, schemes: &[&str]) -> Option<String> {
+        let value = self.get(key).filter(|v| !v.trim().is_empty())?;
+        Some(self.check_url(key, value, schemes))
+    }
+
+    fn check_url(&mut self, key: &str, value: String, schemes: &[&str]) -> String {
+        let name = Self::env_name(key);
+        match Url::parse(&value) {
+            Ok(url) if schemes.contains(&url.scheme()) => {}
+            Ok(url) => self.errors.push(format!(
+                "Invalid {name}: scheme \"{}\" is not one of {}",
+                url.scheme(),
+                schemes.join(", ")
+            )),
+            // don't echo the value, it may contain credentials
+            Err(e) => self.errors.push(format!("Invalid {name}: {e}")),
+        }
+        // trailing slashes would produce `//` when joining paths
+        value.trim_end_matches('/').to_string()
+    }
+
+    /// Accepts `YYYY-MM-DD` (midnight UTC) or an RFC 3339 timestamp
+    fn optional_date(&mut self, key: &str) -> Option<DateTime<Utc>> {
+        let value = self.get(key).filter(|v| !v.trim().is_empty())?;
+        let value = value.trim();
+        let parsed = DateTime::parse_from_rfc3339(value)
+            .map(|d| d.with_timezone(&Utc))
+            .or_else(|_| {
+                NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                    .map(|d| d.and_time(NaiveTime::MIN).and_utc())
+            });
+        match parsed {
+            Ok(date) => Some(date),
+            Err(_) => {
+                self.errors.push(format!(
+                    "Invalid {}: Received {value}. Expected a date like 2027-01-31",
+                    Self::env_name(key)
+                ));
+                None
+            }
+        }
+    }
+
+    fn bind_addr(&mut self, key: &str, default: &str) -> String {
+        let value = self.string_or(key, default);
+        self.check_bind_addr(key, &value);
+        value
+    }
+
+    fn optional_bind_addr(&mut self, key: &str) -> Option<String> {
+        let value = self.get(key).filter(|v| !v.trim().is_empty())?;
+        self.check_bind_addr(key, &value);
+        Some(value)
+    }
+
+    fn check_bind_addr(&mut self, key: &str, value: &str) {
+        let valid = SocketAddr::from_str(value).is_ok()
+            || value
+                .rsplit_once(':')
+                .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+        if !valid {
+            self.errors.push(format!(
+                "Invalid {}: Received {value}. Expected \"host:port\"",
+                Self::env_name(key)
+            ));
+        }
+    }
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, ConfigError> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(ConfigError::InvalidBool(format!(
+            "Invalid {name}: Received {value}. Expected \"true\" or \"false\""
+        ))),
+    }
+}
+
 impl TryFrom<String> for AppEnv {
     type Error = ConfigError;
 
@@ -76,7 +416,16 @@ impl TryFrom<String> for AppEnv {
 impl std::fmt::Display for ConfigError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ConfigError::InvalidAppEnv(s) => write!(f, "{s}"),
+            ConfigError::InvalidAppEnv(s) | ConfigError::InvalidBool(s) | ConfigError::File(s) => {
+                write!(f, "{s}")
+            }
+            ConfigError::Invalid(errors) => {
+                write!(f, "Invalid configuration:")?;
+                for e in errors {
+                    write!(f, "\n  - {e}")?;
+                }
+                Ok(())
+            }
         }
     }
 }
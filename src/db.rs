@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use sqlx::PgPool;
+use sqlx::migrate::Migrator;
+use sqlx::postgres::PgPoolOptions;
+use tracing::info;
+
+use crate::config::Config;
+
+/// Migrations from `./migrations`, embedded at compile time
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+pub async fn connect(config: &Config) -> Result<PgPool> {
+    let pool = PgPoolOptions::new()
+        .max_connections(config.db_max_connections)
+        .acquire_timeout(Duration::from_secs(config.db_acquire_timeout_secs))
+        .connect(config.database_url.expose_str())
+        .await?;
+    Ok(pool)
+}
+
+/// Apply any pending migrations.
+///
+/// The migrator holds a Postgres advisory lock for the duration of the run,
+/// so replicas starting at the same time wait for each other instead of
+/// racing on the same migration.
+pub async fn run_migrations(pool: &PgPool) -> Result<()> {
+    info!("applying database migrations");
+    MIGRATOR.run(pool).await?;
+    info!("database migrations up to date");
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use chrono::{DateTime, Utc};
+use poem::http::HeaderValue;
+use poem::{Endpoint, IntoResponse, Middleware, Request, Response, Result};
+
+/// Marks every response from the wrapped endpoint as deprecated (RFC 9745),
+/// with an optional `Sunset` date (RFC 8594) and a `Link` to the same path
+/// under the successor version prefix.
+pub struct Deprecation {
+    deprecated_at: DateTime<Utc>,
+    sunset: Option<DateTime<Utc>>,
+    successor_prefix: &'static str,
+}
+
+impl Deprecation {
+    pub fn new(
+        deprecated_at: DateTime<Utc>,
+        sunset: Option<DateTime<Utc>>,
+        successor_prefix: &'static str,
+    ) -> Self {
+        Self {
+            deprecated_at,
+            sunset,
+            successor_prefix,
+        }
+    }
+}
+
+impl<E: Endpoint> Middleware<E> for Deprecation {
+    type Output = DeprecationEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        DeprecationEndpoint {
+            inner: ep,
+            deprecation: HeaderValue::from_str(&format!("@{}", self.deprecated_at.timestamp()))
+                .expect("valid header value"),
+            sunset: self.sunset.map(|sunset| {
+                HeaderValue::from_str(&sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+                    .expect("valid header value")
+            }),
+            successor_prefix: self.successor_prefix,
+        }
+    }
+}
+
+pub struct DeprecationEndpoint<E> {
+    inner: E,
+    deprecation: HeaderValue,
+    sunset: Option<HeaderValue>,
+    successor_prefix: &'static str,
+}
+
+impl<E: Endpoint> Endpoint for DeprecationEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let link = format!(
+            "<{}{}>; rel=\"successor-version\"",
+            self.successor_prefix,
+            req.uri().path()
+        );
+
+        let mut resp = match self.inner.call(req).await {
+            Ok(resp) => resp.into_response(),
+            // handler errors already carry their JSON body; anything else is
+            // left for the error renderer further out
+            Err(err) if err.is_from_response() => err.into_response(),
+            Err(err) => return Err(err),
+        };
+
+        let headers = resp.headers_mut();
+        headers.insert("deprecation", self.deprecation.clone());
+        if let Some(sunset) = &self.sunset {
+            headers.insert("sunset", sunset.clone());
+        }
+        if let Ok(link) = HeaderValue::from_str(&link) {
+            headers.insert("link", link);
+        }
+
+        Ok(resp)
+    }
+}
@@ -0,0 +1,288 @@
+use poem::http::StatusCode;
+use poem::{IntoResponse, PathPattern, Response};
+use poem_openapi::registry::{MetaResponses, Registry};
+use poem_openapi::{ApiResponse, Enum, Object, payload::Json};
+use serde::Serialize;
+use tracing::error;
+
+use crate::clients::inat::InatError;
+use crate::request_id;
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+/// Application error returned by handlers. Every variant maps to a stable
+/// [`ErrorCode`] and an HTTP status, and is rendered as an [`ErrorBody`].
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("not logged in")]
+    Unauthenticated,
+    #[error("session expired")]
+    SessionExpired,
+    #[error("invalid or expired OAuth state")]
+    InvalidOAuthState,
+    #[error("{0}")]
+    Validation(String),
+    #[error("{0} not found")]
+    NotFound(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("redis error: {0}")]
+    Redis(#[from] redis::RedisError),
+    #[error("upstream request failed: {0}")]
+    Upstream(#[from] reqwest::Error),
+    #[error("{0}")]
+    Inat(#[from] InatError),
+    #[error("{0:#}")]
+    Internal(anyhow::Error),
+}
+
+/// Machine-readable error codes. These are part of the public contract, so
+/// existing values must not be renamed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Enum)]
+#[oai(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    Unauthenticated,
+    SessionExpired,
+    InvalidOauthState,
+    ValidationFailed,
+    NotFound,
+    Forbidden,
+    Conflict,
+    MethodNotAllowed,
+    UpstreamUnavailable,
+    UpstreamRateLimited,
+    UpstreamAuthFailed,
+    ServiceUnavailable,
+    Internal,
+}
+
+/// JSON body returned for every error response
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct ErrorBody {
+    /// Stable machine-readable error code
+    pub code: ErrorCode,
+    /// Human-readable description, not meant to be parsed
+    pub message: String,
+    /// Id of the failed request, also sent in the `X-Request-Id` header.
+    /// Include it when reporting a problem.
+    pub request_id: Option<String>,
+}
+
+impl ErrorBody {
+    pub fn new(code: ErrorCode, message: String) -> Self {
+        Self {
+            code,
+            message,
+            request_id: request_id::current(),
+        }
+    }
+}
+
+/// Wire representation of [`ApiError`], used to document error responses in
+/// the OpenAPI spec.
+#[derive(ApiResponse)]
+pub enum ErrorResponse {
+    /// The request was malformed or failed validation
+    #[oai(status = 400)]
+    BadRequest(Json<ErrorBody>),
+    /// Not logged in, or the session has expired
+    #[oai(status = 401)]
+    Unauthorized(Json<ErrorBody>),
+    /// Logged in, but not allowed to do this
+    #[oai(status = 403)]
+    Forbidden(Json<ErrorBody>),
+    /// The requested resource does not exist
+    #[oai(status = 404)]
+    NotFound(Json<ErrorBody>),
+    /// The request conflicts with the current state, e.g. a question that
+    /// was already answered
+    #[oai(status = 409)]
+    Conflict(Json<ErrorBody>),
+    /// Unexpected server error
+    #[oai(status = 500)]
+    Internal(Json<ErrorBody>),
+    /// An upstream service (e.g. iNaturalist) failed or rejected our
+    /// credentials
+    #[oai(status = 502)]
+    BadGateway(Json<ErrorBody>),
+    /// A backing service is temporarily unavailable, or iNaturalist is
+    /// rate limiting us
+    #[oai(status = 503)]
+    ServiceUnavailable(Json<ErrorBody>),
+}
+
+impl ApiError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ApiError::Unauthenticated => ErrorCode::Unauthenticated,
+            ApiError::SessionExpired => ErrorCode::SessionExpired,
+            ApiError::InvalidOAuthState => ErrorCode::InvalidOauthState,
+            ApiError::Validation(_) => ErrorCode::ValidationFailed,
+            ApiError::NotFound(_) => ErrorCode::NotFound,
+            ApiError::Forbidden(_) => ErrorCode::Forbidden,
+            ApiError::Conflict(_) => ErrorCode::Conflict,
+            ApiError::Database(sqlx::Error::PoolTimedOut) => ErrorCode::ServiceUnavailable,
+            ApiError::Redis(e) if e.is_connection_refusal() || e.is_timeout() => {
+                ErrorCode::ServiceUnavailable
+            }
+            ApiError::Database(_) | ApiError::Redis(_) | ApiError::Internal(_) => {
+                ErrorCode::Internal
+            }
+            ApiError::Upstream(_) => ErrorCode::UpstreamUnavailable,
+            ApiError::Inat(
+                InatError::RateLimited { .. }
+                | InatError::Throttled
+                | InatError::DailyBudgetExhausted,
+            ) => ErrorCode::UpstreamRateLimited,
+            ApiError::Inat(InatError::Unauthorized(_)) => ErrorCode::UpstreamAuthFailed,
+            ApiError::Inat(_) => ErrorCode::UpstreamUnavailable,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self.code() {
+            ErrorCode::BadRequest | ErrorCode::InvalidOauthState | ErrorCode::ValidationFailed => {
+                StatusCode::BAD_REQUEST
+            }
+            ErrorCode::Unauthenticated | ErrorCode::SessionExpired => StatusCode::UNAUTHORIZED,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::Conflict => StatusCode::CONFLICT,
+            ErrorCode::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ErrorCode::UpstreamUnavailable | ErrorCode::UpstreamAuthFailed => {
+                StatusCode::BAD_GATEWAY
+            }
+            ErrorCode::UpstreamRateLimited | ErrorCode::ServiceUnavailable => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Message sent to the client. Internal details are only logged.
+    fn public_message(&self) -> String {
+        match self.code() {
+            ErrorCode::Internal => "internal server error".to_string(),
+            ErrorCode::ServiceUnavailable => "service temporarily unavailable".to_string(),
+            ErrorCode::UpstreamUnavailable => "upstream service request failed".to_string(),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        // repos and clients return anyhow, so recover the concrete error where possible
+        let err = match err.downcast::<ApiError>() {
+            Ok(e) => return e,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<sqlx::Error>() {
+            Ok(e) => return ApiError::Database(e),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<redis::RedisError>() {
+            Ok(e) => return ApiError::Redis(e),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<InatError>() {
+            Ok(e) => return ApiError::Inat(e),
+            Err(err) => err,
+        };
+        match err.downcast::<reqwest::Error>() {
+            Ok(e) => ApiError::Upstream(e),
+            Err(err) => ApiError::Internal(err),
+        }
+    }
+}
+
+impl ErrorResponse {
+    pub fn new(status: StatusCode, body: ErrorBody) -> Self {
+        let body = Json(body);
+        match status {
+            StatusCode::UNAUTHORIZED => ErrorResponse::Unauthorized(body),
+            StatusCode::FORBIDDEN => ErrorResponse::Forbidden(body),
+            StatusCode::NOT_FOUND => ErrorResponse::NotFound(body),
+            StatusCode::CONFLICT => ErrorResponse::Conflict(body),
+            StatusCode::BAD_GATEWAY => ErrorResponse::BadGateway(body),
+            StatusCode::SERVICE_UNAVAILABLE => ErrorResponse::ServiceUnavailable(body),
+            s if s.is_client_error() => ErrorResponse::BadRequest(body),
+            _ => ErrorResponse::Internal(body),
+        }
+    }
+}
+
+impl From<ApiError> for ErrorResponse {
+    fn from(err: ApiError) -> Self {
+        let status = err.status();
+        if status.is_server_error() {
+            error!(code = ?err.code(), "{err}");
+        }
+        let body = ErrorBody::new(err.code(), err.public_message());
+        ErrorResponse::new(status, body)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        ErrorResponse::from(self).into_response()
+    }
+}
+
+impl ApiResponse for ApiError {
+    fn meta() -> MetaResponses {
+        ErrorResponse::meta()
+    }
+
+    fn register(registry: &mut Registry) {
+        ErrorResponse::register(registry);
+    }
+}
+
+impl From<ApiError> for poem::Error {
+    fn from(err: ApiError) -> Self {
+        poem::Error::from(ErrorResponse::from(err))
+    }
+}
+
+/// Converts errors raised by poem itself (unknown routes, unparseable query
+/// strings, ...) into the same JSON shape that handlers return.
+pub async fn render_poem_error(err: poem::Error) -> Response {
+    if err.is_from_response() {
+        return err.into_response();
+    }
+
+    let status = err.status();
+    let code = match status {
+        StatusCode::UNAUTHORIZED => ErrorCode::Unauthenticated,
+        StatusCode::FORBIDDEN => ErrorCode::Forbidden,
+        StatusCode::NOT_FOUND => ErrorCode::NotFound,
+        StatusCode::CONFLICT => ErrorCode::Conflict,
+        StatusCode::METHOD_NOT_ALLOWED => ErrorCode::MethodNotAllowed,
+        StatusCode::SERVICE_UNAVAILABLE => ErrorCode::ServiceUnavailable,
+        s if s.is_client_error() => ErrorCode::BadRequest,
+        _ => ErrorCode::Internal,
+    };
+    if status.is_server_error() {
+        error!("unhandled error: {err}");
+    }
+
+    let message = match code {
+        ErrorCode::Internal => "internal server error".to_string(),
+        _ => err.to_string(),
+    };
+    let mut resp = poem::web::Json(ErrorBody::new(code, message))
+        .with_status(status)
+        .into_response();
+    // keep the matched route for metrics and request logs
+    if let Some(pattern) = err.data::<PathPattern>() {
+        resp.set_data(pattern.clone());
+    }
+    resp
+}
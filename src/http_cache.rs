@@ -0,0 +1,141 @@
+use poem::http::{HeaderValue, Method, StatusCode, header};
+use poem::{Body, Endpoint, EndpointExt, IntoResponse, Middleware, Request, Response, Result};
+use sha2::{Digest, Sha256};
+
+/// Per-user data: browsers may keep it, but must revalidate with the ETag
+/// before reuse, and shared caches must not store it.
+pub const PRIVATE: &str = "private, no-cache";
+
+/// Shared reference data such as taxa, which changes rarely and doesn't
+/// depend on who is asking
+pub const PUBLIC: &str = "public, max-age=3600";
+
+/// Applied to any response that didn't set its own policy
+const DEFAULT_POLICY: &str = "no-store";
+
+/// Operation transform for per-user responses, e.g. `/auth/me`
+pub fn cache_private(ep: impl Endpoint) -> impl Endpoint {
+    ep.with(CacheControl(PRIVATE))
+}
+
+/// Operation transform for public reference data, e.g. taxon lookups
+pub fn cache_public(ep: impl Endpoint) -> impl Endpoint {
+    ep.with(CacheControl(PUBLIC))
+}
+
+/// Sets `Cache-Control` on successful responses from the wrapped endpoint,
+/// unless the handler already set one. Used per operation through the
+/// `transform` attribute of `#[oai]`.
+pub struct CacheControl(pub &'static str);
+
+impl<E: Endpoint> Middleware<E> for CacheControl {
+    type Output = CacheControlEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        CacheControlEndpoint {
+            inner: ep,
+            policy: HeaderValue::from_static(self.0),
+        }
+    }
+}
+
+pub struct CacheControlEndpoint<E> {
+    inner: E,
+    policy: HeaderValue,
+}
+
+impl<E: Endpoint> Endpoint for CacheControlEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let mut resp = self.inner.call(req).await?.into_response();
+        if resp.status().is_success() && !resp.headers().contains_key(header::CACHE_CONTROL) {
+            resp.headers_mut()
+                .insert(header::CACHE_CONTROL, self.policy.clone());
+        }
+        Ok(resp)
+    }
+}
+
+/// Adds a strong `ETag` to successful `GET`/`HEAD` responses and answers
+/// `304 Not Modified` when it matches `If-None-Match`. Responses without an
+/// explicit `Cache-Control` policy get `no-store` and no ETag.
+///
+/// The tag is a hash of the uncompressed body, so this must sit inside the
+/// compression middleware.
+pub struct ConditionalGet;
+
+impl<E: Endpoint> Middleware<E> for ConditionalGet {
+    type Output = ConditionalGetEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        ConditionalGetEndpoint { inner: ep }
+    }
+}
+
+pub struct ConditionalGetEndpoint<E> {
+    inner: E,
+}
+
+impl<E: Endpoint> Endpoint for ConditionalGetEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let cacheable = matches!(*req.method(), Method::GET | Method::HEAD);
+        let if_none_match = req.headers().get(header::IF_NONE_MATCH).cloned();
+
+        let mut resp = self.inner.call(req).await?.into_response();
+
+        let policy = match resp.headers().get(header::CACHE_CONTROL) {
+            Some(policy) => policy.clone(),
+            None => {
+                resp.headers_mut().insert(
+                    header::CACHE_CONTROL,
+                    HeaderValue::from_static(DEFAULT_POLICY),
+                );
+                return Ok(resp);
+            }
+        };
+        if !cacheable
+            || resp.status() != StatusCode::OK
+            || policy.as_bytes().starts_with(b"no-store")
+            || resp.headers().contains_key(header::ETAG)
+        {
+            return Ok(resp);
+        }
+
+        let body = resp.take_body().into_bytes().await?;
+        let etag = etag_for(&body);
+
+        if if_none_match.is_some_and(|v| matches_etag(&v, &etag)) {
+            // keep the response's extensions (route pattern) for metrics and logs
+            resp.set_status(StatusCode::NOT_MODIFIED);
+            let headers = resp.headers_mut();
+            headers.remove(header::CONTENT_TYPE);
+            headers.remove(header::CONTENT_LENGTH);
+            headers.insert(header::ETAG, etag);
+            return Ok(resp);
+        }
+
+        resp.headers_mut().insert(header::ETAG, etag);
+        resp.set_body(Body::from_bytes(body));
+        Ok(resp)
+    }
+}
+
+fn etag_for(body: &[u8]) -> HeaderValue {
+    let digest = Sha256::digest(body);
+    let hex: String = digest[..16].iter().map(|b| format!("{b:02x}")).collect();
+    HeaderValue::from_str(&format!("\"{hex}\"")).expect("valid header value")
+}
+
+/// Weak comparison as required for `If-None-Match` (RFC 9110 13.1.2)
+fn matches_etag(if_none_match: &HeaderValue, etag: &HeaderValue) -> bool {
+    let Ok(value) = if_none_match.to_str() else {
+        return false;
+    };
+    let etag = etag.to_str().unwrap_or_default();
+    value.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
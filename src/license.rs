@@ -0,0 +1,154 @@
+//! Licences of photos and observations from iNaturalist, which reports them
+//! as loosely formatted codes (`"cc-by-nc"`, `"CC-BY-NC"`, or null for all
+//! rights reserved).
+
+use std::str::FromStr;
+
+/// A content licence. Anything iNaturalist reports that isn't recognised is
+/// treated as [`License::AllRightsReserved`], so it's never reused by mistake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum License {
+    Cc0,
+    CcBy,
+    CcBySa,
+    CcByNd,
+    CcByNc,
+    CcByNcSa,
+    CcByNcNd,
+    PublicDomain,
+    Gfdl,
+    AllRightsReserved,
+}
+
+/// Licences that allow showing the photo in a public quiz with attribution
+pub const REUSABLE: &[License] = &[
+    License::Cc0,
+    License::CcBy,
+    License::CcBySa,
+    License::CcByNd,
+    License::CcByNc,
+    License::CcByNcSa,
+    License::CcByNcNd,
+    License::PublicDomain,
+    License::Gfdl,
+];
+
+impl License {
+    /// Parses an iNaturalist licence code; `None` means all rights reserved
+    pub fn from_inat(code: Option<&str>) -> Self {
+        code.and_then(|c| c.parse().ok())
+            .unwrap_or(License::AllRightsReserved)
+    }
+
+    /// Normalised code, lowercase and hyphenated like iNaturalist's,
+    /// e.g. "cc-by-nc"
+    pub fn code(self) -> &'static str {
+        match self {
+            License::Cc0 => "cc0",
+            License::CcBy => "cc-by",
+            License::CcBySa => "cc-by-sa",
+            License::CcByNd => "cc-by-nd",
+            License::CcByNc => "cc-by-nc",
+            License::CcByNcSa => "cc-by-nc-sa",
+            License::CcByNcNd => "cc-by-nc-nd",
+            License::PublicDomain => "pd",
+            License::Gfdl => "gfdl",
+            License::AllRightsReserved => "all-rights-reserved",
+        }
+    }
+
+    /// Short name to show next to the attribution, e.g. "CC BY-NC"
+    pub fn name(self) -> &'static str {
+        match self {
+            License::Cc0 => "CC0",
+            License::CcBy => "CC BY",
+            License::CcBySa => "CC BY-SA",
+            License::CcByNd => "CC BY-ND",
+            License::CcByNc => "CC BY-NC",
+            License::CcByNcSa => "CC BY-NC-SA",
+            License::CcByNcNd => "CC BY-NC-ND",
+            License::PublicDomain => "Public domain",
+            License::Gfdl => "GFDL",
+            License::AllRightsReserved => "All rights reserved",
+        }
+    }
+
+    /// The licence text. iNaturalist applies version 4.0 of the Creative
+    /// Commons licences.
+    pub fn url(self) -> Option<&'static str> {
+        let url = match self {
+            License::Cc0 => "https://creativecommons.org/publicdomain/zero/1.0/",
+            License::CcBy => "https://creativecommons.org/licenses/by/4.0/",
+            License::CcBySa => "https://creativecommons.org/licenses/by-sa/4.0/",
+            License::CcByNd => "https://creativecommons.org/licenses/by-nd/4.0/",
+            License::CcByNc => "https://creativecommons.org/licenses/by-nc/4.0/",
+            License::CcByNcSa => "https://creativecommons.org/licenses/by-nc-sa/4.0/",
+            License::CcByNcNd => "https://creativecommons.org/licenses/by-nc-nd/4.0/",
+            License::PublicDomain => "https://creativecommons.org/publicdomain/mark/1.0/",
+            License::Gfdl => "https://www.gnu.org/licenses/fdl-1.3.html",
+            License::AllRightsReserved => return None,
+        };
+        Some(url)
+    }
+
+    pub fn is_reusable(self) -> bool {
+        REUSABLE.contains(&self)
+    }
+
+    pub fn is_creative_commons(self) -> bool {
+        !matches!(
+            self,
+            License::PublicDomain | License::Gfdl | License::AllRightsReserved
+        )
+    }
+
+    /// The phrase iNaturalist uses in attributions
+    fn rights(self) -> &'static str {
+        match self {
+            License::Cc0 | License::PublicDomain => "no rights reserved",
+            License::AllRightsReserved => "all rights reserved",
+            _ => "some rights reserved",
+        }
+    }
+
+    /// A credit line for content under this licence. iNaturalist's own
+    /// attribution is used when there is one, otherwise it's built from
+    /// `owner` in the same format, e.g. "(c) Jane Doe, some rights reserved
+    /// (CC BY-NC)".
+    pub fn attribution(self, inat_attribution: Option<&str>, owner: Option<&str>) -> String {
+        if let Some(attribution) = inat_attribution.map(str::trim).filter(|a| !a.is_empty()) {
+            return attribution.to_string();
+        }
+        let owner = owner
+            .map(str::trim)
+            .filter(|o| !o.is_empty())
+            .unwrap_or("an iNaturalist user");
+        match self {
+            License::AllRightsReserved => format!("(c) {owner}, {}", self.rights()),
+            _ => format!("(c) {owner}, {} ({})", self.rights(), self.name()),
+        }
+    }
+}
+
+impl FromStr for License {
+    type Err = ();
+
+    /// Accepts any case, and underscores or spaces in place of hyphens
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalised = s.trim().to_ascii_lowercase().replace(['_', ' '], "-");
+        let license = match normalised.as_str() {
+            "cc0" | "cc-0" => License::Cc0,
+            "cc-by" => License::CcBy,
+            "cc-by-sa" => License::CcBySa,
+            "cc-by-nd" => License::CcByNd,
+            "cc-by-nc" => License::CcByNc,
+            "cc-by-nc-sa" => License::CcByNcSa,
+            "cc-by-nc-nd" => License::CcByNcNd,
+            "pd" | "public-domain" => License::PublicDomain,
+            "gfdl" => License::Gfdl,
+            "all-rights-reserved" | "c" => License::AllRightsReserved,
+            _ => return Err(()),
+        };
+        Ok(license)
+    }
+}
@@ -1,106 +1,171 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use clap::Parser;
 use dotenvy::dotenv;
-use poem::EndpointExt;
 use poem::middleware::{CookieJarManager, Cors};
+use poem::{EndpointExt, IntoEndpoint};
 use poem::{Route, Server, listener::TcpListener};
 use poem_openapi::OpenApiService;
-use reqwest::StatusCode;
-use sqlx::postgres::PgPoolOptions;
-use tracing::error;
-use tracing_subscriber::filter::EnvFilter;
+use tracing::info;
 
-use crate::config::{AppEnv, Config};
+use crate::cli::{Cli, Command};
+use crate::clients::inat::InatClient;
+use crate::config::Config;
+use crate::deprecation::Deprecation;
+use crate::shutdown::ShutdownState;
 use crate::state::AppState;
 
+pub mod cli;
 pub mod clients;
+pub mod compression;
 pub mod config;
+pub mod db;
+pub mod deprecation;
+pub mod error;
+pub mod http_cache;
+pub mod license;
+pub mod metrics;
 pub mod models;
 pub mod repos;
+pub mod request_id;
 pub mod routes;
+pub mod scrub;
+pub mod secret;
 pub mod services;
 pub mod session_store;
+pub mod shutdown;
 pub mod state;
-
-pub fn internal_error<E: std::fmt::Display>(context: &'static str, err: E) -> poem::Error {
-    error!("{context}: {err}");
-    poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR)
-}
+pub mod telemetry;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
     // doesn't do anything in production, since env vars are included in the process
     let _ = dotenv();
-    let config = Config::from_env()?;
+    let config = Config::load()?;
+
+    let _telemetry = telemetry::init_tracing(&config)?;
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config).await,
+        Command::Migrate => cli::migrate(&config).await,
+        Command::CreateAdmin {
+            display_name,
+            email,
+        } => cli::create_admin(&config, &display_name, email.as_deref()).await,
+        Command::RevokeSessions { user } => cli::revoke_sessions(&config, user).await,
+        Command::DeleteUser { user } => cli::delete_user(&config, user).await,
+        Command::PurgeDeletedUsers {
+            older_than_days,
+            dry_run,
+        } => cli::purge_deleted_users(&config, older_than_days, dry_run).await,
+        Command::RefreshTaxa {
+            older_than_days,
+            limit,
+        } => cli::refresh_taxa(&config, older_than_days, limit).await,
+        Command::ImportTaxonomy {
+            path,
+            batch_size,
+            restart,
+        } => cli::import_taxonomy(&config, &path, batch_size, restart).await,
+        Command::CheckConfig => {
+            cli::check_config(&config);
+            Ok(())
+        }
+    }
+}
+
+/// When `/v1` was introduced and the unversioned routes became deprecated
+const LEGACY_API_DEPRECATED_AT: DateTime<Utc> =
+    DateTime::from_timestamp(1_792_368_000, 0).expect("valid timestamp");
+
+async fn serve(config: Config) -> anyhow::Result<()> {
     // just clone first to avoid borrow issues
     let state_config = config.clone();
 
-    init_tracing(&config);
+    let pool = db::connect(&config).await?;
+    if config.run_migrations {
+        db::run_migrations(&pool).await?;
+    }
 
-    let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&config.database_url)
-        .await?;
-    let redis_client = redis::Client::open(config.redis_url)?;
+    let redis_client = redis::Client::open(config.redis_url.expose_str())?;
+
+    let inat = InatClient::new(&config, redis_client.clone())?;
 
-    let state = AppState::new(pool, redis_client, state_config);
+    let shutdown = ShutdownState::default();
+    let state = AppState::new(
+        pool.clone(),
+        redis_client,
+        inat,
+        state_config,
+        shutdown.clone(),
+    );
 
     let cors = Cors::new()
         .allow_credentials(true)
         .allow_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"])
-        .allow_headers(vec!["Content-Type", "Authorization"])
+        .allow_headers(vec!["Content-Type", "Authorization", "X-Request-Id"])
+        .expose_headers(vec!["X-Request-Id", "ETag"])
         .allow_origins(config.allowed_origins);
 
-    let api_service = OpenApiService::new(
-        (
-            routes::health_check::HealthCheckApi {
-                state: state.clone(),
-            },
-            routes::auth::AuthApi {
-                state: state.clone(),
-            },
-            routes::quiz::QuizApi {
-                state: state.clone(),
-            },
-        ),
-        "Taxonia API",
-        "1.0",
-    )
-    .server(format!("{}", config.bind_addr));
+    let api_v1 = OpenApiService::new(routes::v1(&state), "Taxonia API", "1.0")
+        .server(format!("{}/v1", config.base_url));
 
     // Swagger UI for testing & docs
-    let swagger = api_service.swagger_ui();
-    let spec = api_service.spec_endpoint();
+    let swagger = api_v1.swagger_ui();
+    let spec = api_v1.spec_endpoint();
+
+    // the pre-`/v1` operations at their original unversioned paths, until
+    // sunset
+    let legacy = OpenApiService::new(routes::legacy(&state), "Taxonia API", "1.0")
+        .into_endpoint()
+        .with(Deprecation::new(
+            LEGACY_API_DEPRECATED_AT,
+            config.legacy_api_sunset,
+            "/v1",
+        ));
 
     // Mount everything
     let api = Route::new()
-        .nest("/", api_service)
+        .nest("/v1", api_v1)
+        .nest("/", legacy)
         .nest("/spec", swagger)
         .nest("/spec.json", spec)
         .with(CookieJarManager::new())
-        .with(cors);
+        .with(cors)
+        .catch_all_error(error::render_poem_error)
+        .with(http_cache::ConditionalGet)
+        .with(compression::Compression)
+        .with(metrics::HttpMetrics)
+        .with(request_id::RequestContext);
+
+    // metrics live on a separate admin listener that isn't routed publicly
+    let metrics_server = config.metrics_bind_addr.clone().map(|addr| {
+        let pool = pool.clone();
+        let app = Route::new().at(
+            "/metrics",
+            poem::get(poem::endpoint::make_sync(move |_| metrics::render(&pool))),
+        );
+        info!("serving metrics on {addr}");
+        tokio::spawn(Server::new(TcpListener::bind(addr)).run(app))
+    });
 
     Server::new(TcpListener::bind(config.bind_addr))
-        .run(api)
+        .run_with_graceful_shutdown(
+            api,
+            shutdown::signal(shutdown, Duration::from_secs(config.shutdown_delay_secs)),
+            Some(Duration::from_secs(config.shutdown_drain_timeout_secs)),
+        )
         .await?;
 
-    Ok(())
-}
-
-fn init_tracing(config: &Config) {
-    let env_filter = EnvFilter::from_default_env()
-        .add_directive("sqlx=info".parse().unwrap())
-        .add_directive("taxonia_api=debug".parse().unwrap());
-
-    if config.app_env == AppEnv::Production {
-        tracing_subscriber::fmt()
-            .with_env_filter(env_filter)
-            .json()
-            .with_current_span(false)
-            .with_span_events(tracing_subscriber::fmt::format::FmtSpan::NONE)
-            .init();
-    } else {
-        tracing_subscriber::fmt()
-            .with_env_filter(env_filter)
-            .pretty()
-            .init();
+    if let Some(handle) = metrics_server {
+        handle.abort();
     }
+
+    info!("server stopped, closing database pool");
+    pool.close().await;
+
+    Ok(())
 }
@@ -0,0 +1,217 @@
+use std::sync::LazyLock;
+use std::time::Instant;
+
+use poem::{Endpoint, IntoResponse, Middleware, PathPattern, Request, Response, Result};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use sqlx::PgPool;
+
+/// Process-wide metrics, exposed in the Prometheus text format by [`render`]
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub db_pool_connections: IntGauge,
+    pub db_pool_idle: IntGauge,
+    pub redis_command_duration: HistogramVec,
+    pub inat_requests: IntCounterVec,
+    pub inat_retries: IntCounterVec,
+    pub inat_circuit_open: IntGauge,
+    pub inat_throttled: IntCounterVec,
+    pub inat_rate_limit_wait: Histogram,
+    pub inat_cache_requests: IntCounterVec,
+    pub logins: IntCounter,
+    pub quiz_results_saved: IntCounter,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry =
+            Registry::new_custom(Some("taxonia".to_string()), None).expect("valid registry prefix");
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency by route",
+            ),
+            &["method", "route"],
+        )
+        .unwrap();
+        let db_pool_connections =
+            IntGauge::new("db_pool_connections", "Open Postgres connections").unwrap();
+        let db_pool_idle =
+            IntGauge::new("db_pool_idle_connections", "Idle Postgres connections").unwrap();
+        let redis_command_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "redis_command_duration_seconds",
+                "Redis operation latency, including acquiring a connection",
+            )
+            .buckets(vec![
+                0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+            ]),
+            &["operation"],
+        )
+        .unwrap();
+        let inat_requests = IntCounterVec::new(
+            Opts::new("inat_requests_total", "iNaturalist API calls by outcome"),
+            &["endpoint", "outcome"],
+        )
+        .unwrap();
+        let inat_retries = IntCounterVec::new(
+            Opts::new("inat_retries_total", "Retried iNaturalist API calls"),
+            &["endpoint"],
+        )
+        .unwrap();
+        let inat_circuit_open = IntGauge::new(
+            "inat_circuit_open",
+            "1 while iNaturalist calls are failing fast after repeated errors",
+        )
+        .unwrap();
+        let inat_throttled = IntCounterVec::new(
+            Opts::new(
+                "inat_throttled_total",
+                "iNaturalist calls delayed or refused by our own rate limiter",
+            ),
+            &["result"],
+        )
+        .unwrap();
+        let inat_rate_limit_wait = Histogram::with_opts(
+            HistogramOpts::new(
+                "inat_rate_limit_wait_seconds",
+                "Time iNaturalist calls spent queued for the rate limiter",
+            )
+            .buckets(vec![0.0, 0.1, 0.25, 0.5, 1.0, 2.0, 3.0, 5.0, 10.0]),
+        )
+        .unwrap();
+        let inat_cache_requests = IntCounterVec::new(
+            Opts::new(
+                "inat_cache_requests_total",
+                "iNaturalist response cache lookups by result (hit, stale, miss, coalesced, error)",
+            ),
+            &["endpoint", "result"],
+        )
+        .unwrap();
+        let logins = IntCounter::new("logins_total", "Successful iNaturalist logins").unwrap();
+        let quiz_results_saved =
+            IntCounter::new("quiz_results_saved_total", "Quiz results saved").unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_pool_connections.clone()))
+            .unwrap();
+        registry.register(Box::new(db_pool_idle.clone())).unwrap();
+        registry
+            .register(Box::new(redis_command_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(inat_requests.clone())).unwrap();
+        registry.register(Box::new(inat_retries.clone())).unwrap();
+        registry
+            .register(Box::new(inat_circuit_open.clone()))
+            .unwrap();
+        registry.register(Box::new(inat_throttled.clone())).unwrap();
+        registry
+            .register(Box::new(inat_rate_limit_wait.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(inat_cache_requests.clone()))
+            .unwrap();
+        registry.register(Box::new(logins.clone())).unwrap();
+        registry
+            .register(Box::new(quiz_results_saved.clone()))
+            .unwrap();
+
+        Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            db_pool_connections,
+            db_pool_idle,
+            redis_command_duration,
+            inat_requests,
+            inat_retries,
+            inat_circuit_open,
+            inat_throttled,
+            inat_rate_limit_wait,
+            inat_cache_requests,
+            logins,
+            quiz_results_saved,
+        }
+    }
+
+    /// Starts a timer that records into `redis_command_duration` when dropped
+    pub fn redis_timer(&self, operation: &str) -> prometheus::HistogramTimer {
+        self.redis_command_duration
+            .with_label_values(&[operation])
+            .start_timer()
+    }
+}
+
+/// Encode all metrics in the Prometheus text format. Pool gauges are sampled
+/// at scrape time.
+pub fn render(pool: &PgPool) -> String {
+    METRICS.db_pool_connections.set(pool.size() as i64);
+    METRICS.db_pool_idle.set(pool.num_idle() as i64);
+
+    let mut buf = Vec::new();
+    TextEncoder::new()
+        .encode(&METRICS.registry.gather(), &mut buf)
+        .expect("text encoding never fails");
+    String::from_utf8(buf).expect("text encoding is utf8")
+}
+
+/// Records request counts and latencies, labelled by the matched route
+/// pattern (e.g. `/quiz/results`) to keep label cardinality bounded.
+pub struct HttpMetrics;
+
+impl<E: Endpoint> Middleware<E> for HttpMetrics {
+    type Output = HttpMetricsEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        HttpMetricsEndpoint { inner: ep }
+    }
+}
+
+pub struct HttpMetricsEndpoint<E> {
+    inner: E,
+}
+
+impl<E: Endpoint> Endpoint for HttpMetricsEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let method = req.method().to_string();
+        let start = Instant::now();
+
+        let result = self.inner.call(req).await.map(IntoResponse::into_response);
+
+        let (route, status) = match &result {
+            Ok(resp) => (resp.data::<PathPattern>().cloned(), resp.status()),
+            Err(err) => (err.data::<PathPattern>().cloned(), err.status()),
+        };
+        let route = route.map(|p| p.0.to_string()).filter(|r| !r.is_empty());
+        let route = route.as_deref().unwrap_or("unmatched");
+
+        METRICS
+            .http_requests
+            .with_label_values(&[method.as_str(), route, status.as_str()])
+            .inc();
+        METRICS
+            .http_request_duration
+            .with_label_values(&[method.as_str(), route])
+            .observe(start.elapsed().as_secs_f64());
+
+        result
+    }
+}
@@ -1,3 +1,5 @@
 pub mod auth_repo;
+pub mod observation_repo;
 pub mod quiz_repo;
+pub mod taxon_repo;
 pub mod user_repo;
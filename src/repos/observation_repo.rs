@@ -0,0 +1,49 @@
+use anyhow::Result;
+use sqlx::PgPool;
+use tracing::instrument;
+
+#[derive(Clone)]
+pub struct ObservationRepo {
+    pool: PgPool,
+}
+
+impl ObservationRepo {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Which of `observation_ids` the user has already been shown
+    #[instrument(name = "db.list_seen_observations", skip(self, observation_ids))]
+    pub async fn list_seen(&self, user_id: i64, observation_ids: &[i64]) -> Result<Vec<i64>> {
+        let ids: Vec<(i64,)> = sqlx::query_as(
+            r#"
+            SELECT observation_id
+            FROM seen_observations
+            WHERE user_id = $1 AND observation_id = ANY($2)
+            "#,
+        )
+        .bind(user_id)
+        .bind(observation_ids)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(ids.into_iter().map(|(id,)| id).collect())
+    }
+
+    #[instrument(name = "db.mark_observations_seen", skip(self, observation_ids))]
+    pub async fn mark_seen(&self, user_id: i64, observation_ids: &[i64]) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO seen_observations (user_id, observation_id)
+            SELECT $1, unnest($2::bigint[])
+            ON CONFLICT (user_id, observation_id) DO UPDATE SET seen_at = now()
+            "#,
+        )
+        .bind(user_id)
+        .bind(observation_ids)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -1,6 +1,24 @@
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use sqlx::types::Json;
 use sqlx::{FromRow, PgPool};
+use tracing::instrument;
+
+use crate::clients::inat::InatPhoto;
+
+const QUESTION_COLUMNS: &str = r#"
+    position,
+    observation_id,
+    observer,
+    photos,
+    correct_taxon_id,
+    choices,
+    chosen_taxon_id,
+    is_correct,
+    answered_at,
+    response_ms
+"#;
 
 #[derive(Clone)]
 pub struct QuizRepo {
@@ -12,6 +30,8 @@ impl QuizRepo {
         Self { pool }
     }
 
+    #[allow(clippy::too_many_arguments)]
+    #[instrument(name = "db.insert_quiz_result", skip_all)]
     pub async fn insert_quiz_result(
         &self,
         user_id: i64,
@@ -20,7 +40,9 @@ impl QuizRepo {
         score: f64,
         question_count: Option<i32>,
         duration_seconds: Option<i32>,
+        answers: &[QuizAnswerRow],
     ) -> Result<i64> {
+        let mut tx = self.pool.begin().await?;
         let rec: (i64,) = sqlx::query_as(
             r#"
             INSERT INTO quiz_results (
@@ -36,12 +58,94 @@ impl QuizRepo {
         .bind(score)
         .bind(question_count)
         .bind(duration_seconds)
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
 
+        let positions: Vec<_> = answers.iter().map(|a| a.position).collect();
+        let taxon_ids: Vec<_> = answers.iter().map(|a| a.taxon_id).collect();
+        let chosen_taxon_ids: Vec<_> = answers.iter().map(|a| a.chosen_taxon_id).collect();
+        let correct: Vec<_> = answers.iter().map(|a| a.correct).collect();
+        let response_ms: Vec<_> = answers.iter().map(|a| a.response_ms).collect();
+        let observation_ids: Vec<_> = answers.iter().map(|a| a.observation_id).collect();
+        let photo_ids: Vec<_> = answers.iter().map(|a| a.photo_id).collect();
+        sqlx::query(
+            r#"
+            INSERT INTO quiz_answers (
+                result_id, position, taxon_id, chosen_taxon_id, correct,
+                response_ms, observation_id, photo_id
+            )
+            SELECT $1, *
+            FROM unnest(
+                $2::int[], $3::bigint[], $4::bigint[], $5::bool[],
+                $6::int[], $7::bigint[], $8::bigint[]
+            )
+            "#,
+        )
+        .bind(rec.0)
+        .bind(&positions)
+        .bind(&taxon_ids)
+        .bind(&chosen_taxon_ids)
+        .bind(&correct)
+        .bind(&response_ms)
+        .bind(&observation_ids)
+        .bind(&photo_ids)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
         Ok(rec.0)
     }
 
+    #[instrument(name = "db.get_quiz_result", skip(self))]
+    pub async fn get_quiz_result(&self, user_id: i64, id: i64) -> Result<Option<QuizResultRow>> {
+        let row = sqlx::query_as::<_, QuizResultRow>(
+            r#"
+            SELECT
+                id,
+                quiz_type,
+                params,
+                score,
+                question_count,
+                duration_seconds,
+                verified,
+                created_at
+            FROM quiz_results
+            WHERE id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row)
+    }
+
+    #[instrument(name = "db.list_quiz_answers", skip(self))]
+    pub async fn list_answers(&self, result_id: i64) -> Result<Vec<QuizAnswerRow>> {
+        let rows = sqlx::query_as::<_, QuizAnswerRow>(
+            r#"
+            SELECT
+                position,
+                taxon_id,
+                chosen_taxon_id,
+                correct,
+                response_ms,
+                observation_id,
+                photo_id
+            FROM quiz_answers
+            WHERE result_id = $1
+            ORDER BY position
+            "#,
+        )
+        .bind(result_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    #[instrument(name = "db.list_quiz_results_for_user", skip_all)]
     pub async fn list_quiz_results_for_user(
         &self,
         user_id: i64,
@@ -57,6 +161,7 @@ impl QuizRepo {
                 score,
                 question_count,
                 duration_seconds,
+                verified,
                 created_at
             FROM quiz_results
             WHERE user_id = $1
@@ -72,6 +177,232 @@ impl QuizRepo {
 
         Ok(rows)
     }
+
+    /// Store a generated quiz and its questions, numbered in order
+    #[instrument(name = "db.insert_quiz_session", skip_all, fields(questions = questions.len()))]
+    pub async fn insert_session(
+        &self,
+        id: &str,
+        user_id: Option<i64>,
+        params: &Value,
+        questions: &[QuizQuestionRow],
+    ) -> Result<QuizSessionRow> {
+        let mut tx = self.pool.begin().await?;
+        let session = sqlx::query_as::<_, QuizSessionRow>(
+            r#"
+            INSERT INTO quiz_sessions (id, user_id, params)
+            VALUES ($1, $2, $3)
+            RETURNING id, user_id, params, created_at, completed_at, score
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(params)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        for question in questions {
+            sqlx::query(
+                r#"
+                INSERT INTO quiz_questions (
+                    session_id, position, observation_id, observer, photos,
+                    correct_taxon_id, choices
+                )
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                "#,
+            )
+            .bind(id)
+            .bind(question.position)
+            .bind(question.observation_id)
+            .bind(&question.observer)
+            .bind(&question.photos)
+            .bind(question.correct_taxon_id)
+            .bind(&question.choices)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(session)
+    }
+
+    #[instrument(name = "db.get_quiz_session", skip(self))]
+    pub async fn get_session(&self, id: &str) -> Result<Option<QuizSessionRow>> {
+        let row = sqlx::query_as::<_, QuizSessionRow>(
+            r#"
+            SELECT id, user_id, params, created_at, completed_at, score
+            FROM quiz_sessions
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row)
+    }
+
+    #[instrument(name = "db.list_quiz_questions", skip(self))]
+    pub async fn list_questions(&self, session_id: &str) -> Result<Vec<QuizQuestionRow>> {
+        let rows = sqlx::query_as::<_, QuizQuestionRow>(&format!(
+            r#"
+            SELECT {QUESTION_COLUMNS}
+            FROM quiz_questions
+            WHERE session_id = $1
+            ORDER BY position
+            "#
+        ))
+        .bind(session_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    /// Record the answer to a question, timed from the previous answer (or
+    /// the quiz's creation). Only the first unanswered question can be
+    /// answered, with one of its choices, so `None` means the question
+    /// doesn't exist, was already answered, isn't next, or doesn't offer
+    /// that taxon.
+    #[instrument(name = "db.answer_quiz_question", skip(self))]
+    pub async fn answer_question(
+        &self,
+        session_id: &str,
+        position: i32,
+        chosen_taxon_id: i64,
+    ) -> Result<Option<QuizQuestionRow>> {
+        let row = sqlx::query_as::<_, QuizQuestionRow>(&format!(
+            r#"
+            UPDATE quiz_questions
+            SET chosen_taxon_id = $3,
+                is_correct = correct_taxon_id = $3,
+                answered_at = clock_timestamp(),
+                response_ms = LEAST(
+                    EXTRACT(EPOCH FROM clock_timestamp() - COALESCE(
+                        (SELECT max(answered_at) FROM quiz_questions WHERE session_id = $1),
+                        (SELECT created_at FROM quiz_sessions WHERE id = $1)
+                    )) * 1000,
+                    2147483647
+                )::integer
+            WHERE session_id = $1
+                AND position = $2
+                AND answered_at IS NULL
+                AND choices @> jsonb_build_array(jsonb_build_object('taxon_id', $3))
+                AND position = (
+                    SELECT min(position)
+                    FROM quiz_questions
+                    WHERE session_id = $1 AND answered_at IS NULL
+                )
+            RETURNING {QUESTION_COLUMNS}
+            "#
+        ))
+        .bind(session_id)
+        .bind(position)
+        .bind(chosen_taxon_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row)
+    }
+
+    /// Mark a fully answered quiz as completed, compute its score, and save a
+    /// verified result with its answers for its owner. `None` if questions
+    /// are still unanswered or it was already completed.
+    #[instrument(name = "db.complete_quiz_session", skip(self))]
+    pub async fn complete_session(
+        &self,
+        session_id: &str,
+        quiz_type: &str,
+    ) -> Result<Option<CompletedQuiz>> {
+        let mut tx = self.pool.begin().await?;
+        let completed: Option<(Option<i64>, f64, Value, i32, i32)> = sqlx::query_as(
+            r#"
+            UPDATE quiz_sessions s
+            SET completed_at = now(),
+                score = stats.score
+            FROM (
+                SELECT
+                    avg(is_correct::int)::double precision AS score,
+                    count(*)::integer AS question_count
+                FROM quiz_questions
+                WHERE session_id = $1
+            ) stats
+            WHERE s.id = $1
+                AND s.completed_at IS NULL
+                AND NOT EXISTS (
+                    SELECT 1 FROM quiz_questions
+                    WHERE session_id = $1 AND answered_at IS NULL
+                )
+            RETURNING
+                s.user_id,
+                s.score,
+                s.params,
+                stats.question_count,
+                EXTRACT(EPOCH FROM now() - s.created_at)::integer
+            "#,
+        )
+        .bind(session_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some((user_id, score, params, question_count, duration_seconds)) = completed else {
+            return Ok(None);
+        };
+
+        let result_id = match user_id {
+            Some(user_id) => {
+                let (id,): (i64,) = sqlx::query_as(
+                    r#"
+                    INSERT INTO quiz_results (
+                        user_id, quiz_type, params, score, question_count,
+                        duration_seconds, verified, session_id
+                    )
+                    VALUES ($1, $2, $3, $4, $5, $6, TRUE, $7)
+                    RETURNING id
+                    "#,
+                )
+                .bind(user_id)
+                .bind(quiz_type)
+                .bind(&params)
+                .bind(score)
+                .bind(question_count)
+                .bind(duration_seconds)
+                .bind(session_id)
+                .fetch_one(&mut *tx)
+                .await?;
+
+                // the first photo is the one shown first in the question
+                sqlx::query(
+                    r#"
+                    INSERT INTO quiz_answers (
+                        result_id, position, taxon_id, chosen_taxon_id, correct,
+                        response_ms, observation_id, photo_id
+                    )
+                    SELECT
+                        $1,
+                        position,
+                        correct_taxon_id,
+                        chosen_taxon_id,
+                        is_correct,
+                        response_ms,
+                        observation_id,
+                        (photos -> 0 ->> 'id')::bigint
+                    FROM quiz_questions
+                    WHERE session_id = $2
+                    "#,
+                )
+                .bind(id)
+                .bind(session_id)
+                .execute(&mut *tx)
+                .await?;
+                Some(id)
+            }
+            None => None,
+        };
+        tx.commit().await?;
+
+        Ok(Some(CompletedQuiz { score, result_id }))
+    }
 }
 
 #[derive(FromRow)]
@@ -82,5 +413,121 @@ pub struct QuizResultRow {
     pub score: f64,
     pub question_count: Option<i32>,
     pub duration_seconds: Option<i32>,
+    /// Computed by the server from a quiz session, rather than posted by
+    /// the client
+    pub verified: bool,
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
+
+#[derive(FromRow)]
+pub struct QuizSessionRow {
+    pub id: String,
+    pub user_id: Option<i64>,
+    pub params: Value,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Set once every question has been answered
+    pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Fraction answered correctly, set on completion
+    pub score: Option<f64>,
+}
+
+/// A quiz session that was just completed
+#[derive(FromRow, Debug)]
+pub struct CompletedQuiz {
+    pub score: f64,
+    /// The verified result saved for the quiz's owner; `None` for anonymous
+    /// quizzes
+    pub result_id: Option<i64>,
+}
+
+#[derive(FromRow, Debug, Clone)]
+pub struct QuizQuestionRow {
+    pub position: i32,
+    pub observation_id: i64,
+    /// Credited in photo attributions iNaturalist didn't provide
+    pub observer: Option<String>,
+    pub photos: Json<Vec<InatPhoto>>,
+    pub correct_taxon_id: i64,
+    /// In display order
+    pub choices: Json<Vec<QuizChoice>>,
+    /// The rest are `None` until the question is answered
+    pub chosen_taxon_id: Option<i64>,
+    pub is_correct: Option<bool>,
+    pub answered_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Since the previous answer, or since the quiz was created
+    pub response_ms: Option<i32>,
+}
+
+/// How one question of a saved result was answered
+#[derive(FromRow, Debug, Clone)]
+pub struct QuizAnswerRow {
+    /// 0-based position in the quiz
+    pub position: i32,
+    /// The correct answer
+    pub taxon_id: i64,
+    /// `None` if the question was skipped
+    pub chosen_taxon_id: Option<i64>,
+    pub correct: bool,
+    pub response_ms: Option<i32>,
+    pub observation_id: Option<i64>,
+    pub photo_id: Option<i64>,
+}
+
+/// A taxon offered as an answer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuizChoice {
+    pub taxon_id: i64,
+    pub name: String,
+    pub rank: Option<String>,
+    pub common_name: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn saves_answers_with_the_result(pool: PgPool) {
+        let user_id: i64 =
+            sqlx::query_scalar("INSERT INTO users (display_name) VALUES ('tester') RETURNING id")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        let answers = vec![
+            QuizAnswerRow {
+                position: 0,
+                taxon_id: 3,
+                chosen_taxon_id: Some(3),
+                correct: true,
+                response_ms: Some(1200),
+                observation_id: Some(10),
+                photo_id: Some(20),
+            },
+            QuizAnswerRow {
+                position: 1,
+                taxon_id: 4,
+                chosen_taxon_id: None,
+                correct: false,
+                response_ms: None,
+                observation_id: None,
+                photo_id: None,
+            },
+        ];
+        let repo = QuizRepo::new(pool);
+
+        let id = repo
+            .insert_quiz_result(user_id, "test", &json!({}), 0.5, Some(2), None, &answers)
+            .await
+            .unwrap();
+        let saved = repo.list_answers(id).await.unwrap();
+
+        assert_eq!(format!("{saved:?}"), format!("{answers:?}"));
+        let empty = repo
+            .insert_quiz_result(user_id, "test", &json!({}), 0.5, None, None, &[])
+            .await
+            .unwrap();
+        assert!(repo.list_answers(empty).await.unwrap().is_empty());
+    }
+}
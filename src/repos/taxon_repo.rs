@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::types::Json;
+use sqlx::{FromRow, PgPool};
+use tracing::instrument;
+
+use crate::clients::inat::InatPhoto;
+
+#[derive(Clone)]
+pub struct TaxonRepo {
+    pool: PgPool,
+}
+
+#[derive(FromRow, Debug, Clone)]
+pub struct TaxonRow {
+    pub id: i64,
+    pub parent_id: Option<i64>,
+    /// Dot-separated ids from the root down to this taxon
+    pub path: String,
+    pub rank: Option<String>,
+    pub rank_level: Option<f64>,
+    pub name: String,
+    /// Locale -> common name
+    pub common_names: Json<HashMap<String, String>>,
+    pub default_photo: Option<Json<InatPhoto>>,
+    pub iconic_taxon_name: Option<String>,
+    pub observations_count: Option<i64>,
+    pub wikipedia_url: Option<String>,
+    pub is_active: bool,
+    /// `None` for taxa only stored as another taxon's ancestor
+    pub fetched_at: Option<DateTime<Utc>>,
+}
+
+impl TaxonRow {
+    /// Ids from the root down to the parent
+    pub fn ancestor_ids(&self) -> Vec<i64> {
+        self.path
+            .split('.')
+            .filter_map(|id| id.parse().ok())
+            .filter(|&id| id != self.id)
+            .collect()
+    }
+}
+
+/// A taxon from a bulk import, without the details only the API provides
+#[derive(Debug, Clone)]
+pub struct ImportedTaxon {
+    pub id: i64,
+    pub parent_id: Option<i64>,
+    pub path: String,
+    pub rank: Option<String>,
+    pub rank_level: Option<f64>,
+    pub name: String,
+}
+
+/// How far an interrupted import got
+#[derive(FromRow, Debug, Clone)]
+pub struct ImportCheckpoint {
+    pub file: String,
+    /// Rows of `file` already imported
+    pub row: i64,
+}
+
+const COLUMNS: &str = r#"
+    id,
+    parent_id,
+    path::text AS path,
+    rank,
+    rank_level,
+    name,
+    common_names,
+    default_photo,
+    iconic_taxon_name,
+    observations_count,
+    wikipedia_url,
+    is_active,
+    fetched_at
+"#;
+
+impl TaxonRepo {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    #[instrument(name = "db.get_taxon", skip(self))]
+    pub async fn get(&self, id: i64) -> Result<Option<TaxonRow>> {
+        let row =
+            sqlx::query_as::<_, TaxonRow>(&format!("SELECT {COLUMNS} FROM taxa WHERE id = $1"))
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(row)
+    }
+
+    /// The taxa with the given ids, in the same order; missing ids are skipped
+    #[instrument(name = "db.get_taxa", skip_all)]
+    pub async fn get_many(&self, ids: &[i64]) -> Result<Vec<TaxonRow>> {
+        let rows = sqlx::query_as::<_, TaxonRow>(&format!(
+            r#"
+            SELECT {COLUMNS}
+            FROM taxa
+            JOIN unnest($1::bigint[]) WITH ORDINALITY AS wanted(id, position) USING (id)
+            ORDER BY wanted.position
+            "#
+        ))
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    /// Mirrored taxa below `id` in the tree, optionally of a single rank,
+    /// most observed first
+    #[instrument(name = "db.list_descendants", skip(self))]
+    pub async fn list_descendants(
+        &self,
+        id: i64,
+        rank: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<TaxonRow>> {
+        let rows = sqlx::query_as::<_, TaxonRow>(&format!(
+            r#"
+            SELECT {COLUMNS}
+            FROM taxa
+            WHERE path <@ (SELECT path FROM taxa WHERE id = $1)
+                AND id <> $1
+                AND ($2::text IS NULL OR rank = $2)
+            ORDER BY observations_count DESC NULLS LAST, id
+            LIMIT $3 OFFSET $4
+            "#
+        ))
+        .bind(id)
+        .bind(rank)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    /// Insert or update taxa. Common names are merged with the stored ones,
+    /// and missing photos or counts don't erase known values, so partial
+    /// records (e.g. ancestors) never overwrite richer data.
+    #[instrument(name = "db.upsert_taxa", skip_all, fields(count = taxa.len()))]
+    pub async fn upsert(&self, taxa: &[TaxonRow]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for taxon in taxa {
+            sqlx::query(
+                r#"
+                INSERT INTO taxa (
+                    id, parent_id, path, rank, rank_level, name, common_names,
+                    default_photo, iconic_taxon_name, observations_count, wikipedia_url,
+                    is_active, fetched_at
+                )
+                VALUES ($1, $2, $3::ltree, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+                ON CONFLICT (id) DO UPDATE SET
+                    parent_id = EXCLUDED.parent_id,
+                    path = EXCLUDED.path,
+                    rank = COALESCE(EXCLUDED.rank, taxa.rank),
+                    rank_level = COALESCE(EXCLUDED.rank_level, taxa.rank_level),
+                    name = EXCLUDED.name,
+                    common_names = taxa.common_names || EXCLUDED.common_names,
+                    default_photo = COALESCE(EXCLUDED.default_photo, taxa.default_photo),
+                    iconic_taxon_name = COALESCE(EXCLUDED.iconic_taxon_name, taxa.iconic_taxon_name),
+                    observations_count = COALESCE(EXCLUDED.observations_count, taxa.observations_count),
+                    wikipedia_url = COALESCE(EXCLUDED.wikipedia_url, taxa.wikipedia_url),
+                    is_active = EXCLUDED.is_active,
+                    fetched_at = COALESCE(EXCLUDED.fetched_at, taxa.fetched_at)
+                "#,
+            )
+            .bind(taxon.id)
+            .bind(taxon.parent_id)
+            .bind(&taxon.path)
+            .bind(&taxon.rank)
+            .bind(taxon.rank_level)
+            .bind(&taxon.name)
+            .bind(&taxon.common_names)
+            .bind(&taxon.default_photo)
+            .bind(&taxon.iconic_taxon_name)
+            .bind(taxon.observations_count)
+            .bind(&taxon.wikipedia_url)
+            .bind(taxon.is_active)
+            .bind(taxon.fetched_at)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Ids of taxa never fully fetched, or last fetched before `before`,
+    /// oldest first
+    #[instrument(name = "db.list_stale_taxa", skip(self))]
+    pub async fn list_stale(&self, before: DateTime<Utc>, limit: i64) -> Result<Vec<i64>> {
+        let ids: Vec<(i64,)> = sqlx::query_as(
+            r#"
+            SELECT id
+            FROM taxa
+            WHERE fetched_at IS NULL OR fetched_at < $1
+            ORDER BY fetched_at NULLS FIRST, id
+            LIMIT $2
+            "#,
+        )
+        .bind(before)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(ids.into_iter().map(|(id,)| id).collect())
+    }
+
+    /// Insert or update taxa from a bulk import. Fields the import doesn't
+    /// have are left alone, and `fetched_at` stays unset for new rows so the
+    /// refresh command fills in the rest. If an id appears more than once,
+    /// the last row wins.
+    #[instrument(name = "db.upsert_imported_taxa", skip_all, fields(count = taxa.len()))]
+    pub async fn upsert_imported(&self, taxa: &[ImportedTaxon]) -> Result<()> {
+        let ids: Vec<_> = taxa.iter().map(|t| t.id).collect();
+        let parent_ids: Vec<_> = taxa.iter().map(|t| t.parent_id).collect();
+        let paths: Vec<_> = taxa.iter().map(|t| t.path.as_str()).collect();
+        let ranks: Vec<_> = taxa.iter().map(|t| t.rank.as_deref()).collect();
+        let rank_levels: Vec<_> = taxa.iter().map(|t| t.rank_level).collect();
+        let names: Vec<_> = taxa.iter().map(|t| t.name.as_str()).collect();
+
+        sqlx::query(
+            r#"
+            INSERT INTO taxa (id, parent_id, path, rank, rank_level, name)
+            SELECT DISTINCT ON (id) id, parent_id, path::ltree, rank, rank_level, name
+            FROM unnest(
+                $1::bigint[], $2::bigint[], $3::text[], $4::text[],
+                $5::double precision[], $6::text[]
+            ) WITH ORDINALITY AS t(id, parent_id, path, rank, rank_level, name, position)
+            -- an upsert can't touch the same row twice
+            ORDER BY id, position DESC
+            ON CONFLICT (id) DO UPDATE SET
+                parent_id = EXCLUDED.parent_id,
+                path = EXCLUDED.path,
+                rank = COALESCE(EXCLUDED.rank, taxa.rank),
+                rank_level = COALESCE(EXCLUDED.rank_level, taxa.rank_level),
+                name = EXCLUDED.name
+            "#,
+        )
+        .bind(&ids)
+        .bind(&parent_ids)
+        .bind(&paths)
+        .bind(&ranks)
+        .bind(&rank_levels)
+        .bind(&names)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Add common names, given as (taxon id, locale, name). Names already
+    /// stored for a locale are kept, as is the first one given per locale.
+    /// Names for unknown taxa are ignored.
+    #[instrument(name = "db.merge_common_names", skip_all, fields(count = names.len()))]
+    pub async fn merge_common_names(&self, names: &[(i64, String, String)]) -> Result<()> {
+        let ids: Vec<_> = names.iter().map(|(id, _, _)| *id).collect();
+        let locales: Vec<_> = names.iter().map(|(_, l, _)| l.as_str()).collect();
+        let values: Vec<_> = names.iter().map(|(_, _, n)| n.as_str()).collect();
+
+        sqlx::query(
+            r#"
+            UPDATE taxa t
+            SET common_names = v.names || t.common_names
+            FROM (
+                SELECT id, jsonb_object_agg(locale, name) AS names
+                FROM (
+                    SELECT DISTINCT ON (id, locale) id, locale, name
+                    FROM unnest($1::bigint[], $2::text[], $3::text[])
+                        WITH ORDINALITY AS n(id, locale, name, position)
+                    ORDER BY id, locale, position
+                ) first_names
+                GROUP BY id
+            ) v
+            WHERE t.id = v.id
+            "#,
+        )
+        .bind(&ids)
+        .bind(&locales)
+        .bind(&values)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(name = "db.get_import_checkpoint", skip(self))]
+    pub async fn get_import_checkpoint(&self, source: &str) -> Result<Option<ImportCheckpoint>> {
+        let checkpoint = sqlx::query_as::<_, ImportCheckpoint>(
+            "SELECT file, row_number AS row FROM import_checkpoints WHERE source = $1",
+        )
+        .bind(source)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(checkpoint)
+    }
+
+    #[instrument(name = "db.save_import_checkpoint", skip(self))]
+    pub async fn save_import_checkpoint(&self, source: &str, file: &str, row: i64) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO import_checkpoints (source, file, row_number)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (source) DO UPDATE SET
+                file = EXCLUDED.file,
+                row_number = EXCLUDED.row_number,
+                updated_at = now()
+            "#,
+        )
+        .bind(source)
+        .bind(file)
+        .bind(row)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    #[instrument(name = "db.delete_import_checkpoint", skip(self))]
+    pub async fn delete_import_checkpoint(&self, source: &str) -> Result<()> {
+        sqlx::query("DELETE FROM import_checkpoints WHERE source = $1")
+            .bind(source)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
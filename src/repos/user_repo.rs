@@ -1,6 +1,8 @@
 use crate::clients::inat::{InatUser, TokenWithExpiry};
-use poem::http::StatusCode;
+use crate::secret::Secret;
+use anyhow::Result;
 use sqlx::PgPool;
+use tracing::{debug, instrument};
 
 pub struct UserRepo {
     pool: PgPool,
@@ -11,11 +13,104 @@ impl UserRepo {
         Self { pool }
     }
 
+    /// Create an admin user, or promote the existing user with this email.
+    /// Returns the user id.
+    #[instrument(name = "db.create_admin", skip_all)]
+    pub async fn create_admin(&self, display_name: &str, email: Option<&str>) -> Result<i64> {
+        if let Some(email) = email {
+            let promoted: Option<(i64,)> = sqlx::query_as(
+                r#"
+                UPDATE users
+                SET is_admin = true
+                WHERE primary_email = $1 AND deleted_at IS NULL
+                RETURNING id
+                "#,
+            )
+            .bind(email)
+            .fetch_optional(&self.pool)
+            .await?;
+
+            if let Some((id,)) = promoted {
+                return Ok(id);
+            }
+        }
+
+        let rec: (i64,) = sqlx::query_as(
+            r#"
+            INSERT INTO users (display_name, primary_email, is_admin)
+            VALUES ($1, $2, true)
+            RETURNING id
+            "#,
+        )
+        .bind(display_name)
+        .bind(email)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(rec.0)
+    }
+
+    /// Soft-delete a user, so they can no longer sign in and are purged
+    /// later. Returns false if there's no such user, or they were already
+    /// deleted.
+    #[instrument(name = "db.soft_delete_user", skip(self))]
+    pub async fn soft_delete_user(&self, user_id: i64) -> Result<bool> {
+        let res = sqlx::query(
+            r#"
+            UPDATE users
+            SET deleted_at = now()
+            WHERE id = $1 AND deleted_at IS NULL
+            "#,
+        )
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Ids of users soft-deleted at least `older_than_days` days ago
+    #[instrument(name = "db.list_purgeable_users", skip_all)]
+    pub async fn list_purgeable_users(&self, older_than_days: i32) -> Result<Vec<i64>> {
+        let rows: Vec<(i64,)> = sqlx::query_as(
+            r#"
+            SELECT id
+            FROM users
+            WHERE deleted_at < now() - make_interval(days => $1)
+            "#,
+        )
+        .bind(older_than_days)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.0).collect())
+    }
+
+    /// Hard-delete users. Identities and quiz results cascade.
+    #[instrument(name = "db.purge_users", skip_all)]
+    pub async fn purge_users(&self, user_ids: &[i64]) -> Result<u64> {
+        let res = sqlx::query(
+            r#"
+            DELETE FROM users
+            WHERE id = ANY($1) AND deleted_at IS NOT NULL
+            "#,
+        )
+        .bind(user_ids)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    /// Find or create the user signing in with this iNaturalist account.
+    /// Returns `None` if the account belongs to a soft-deleted user, who
+    /// can't sign in again.
+    #[instrument(name = "db.upsert_inat_user", skip_all)]
     pub async fn upsert_inat_user(
         &self,
         inat_user: &InatUser,
         token: &TokenWithExpiry,
-    ) -> poem::Result<i64> {
+    ) -> Result<Option<i64>> {
         let inat_user_id = inat_user.id;
         let inat_login = inat_user.login.clone();
         let display_name = inat_user.name.clone().unwrap_or_else(|| inat_login.clone());
@@ -27,28 +122,29 @@ impl UserRepo {
         #[derive(Debug, sqlx::FromRow)]
         struct ExistingIdentity {
             user_id: i64,
+            deleted: bool,
         }
 
-        let mut tx =
-            self.pool.begin().await.map_err(|_| {
-                poem::Error::from_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR)
-            })?;
+        let mut tx = self.pool.begin().await?;
 
         // 1: check auth_identity
         let existing: Option<ExistingIdentity> = sqlx::query_as(
             r#"
-            SELECT user_id
-            FROM auth_identities
-            WHERE provider = CAST($1 as auth_provider) AND provider_user_id = $2
+            SELECT ai.user_id, u.deleted_at IS NOT NULL AS deleted
+            FROM auth_identities ai
+            JOIN users u ON u.id = ai.user_id
+            WHERE ai.provider = CAST($1 as auth_provider) AND ai.provider_user_id = $2
             "#,
         )
-        .bind(&provider)
+        .bind(provider)
         .bind(&provider_user_id)
         .fetch_optional(&mut *tx)
-        .await
-        .map_err(|_| poem::Error::from_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR))?;
+        .await?;
 
-        println!("Existing user: {:?}", &existing);
+        debug!("Existing user: {:?}", &existing);
+        if existing.as_ref().is_some_and(|row| row.deleted) {
+            return Ok(None);
+        }
         // 2: update or insert users
         let user_id: i64 = if let Some(row) = existing {
             // update last_used_at
@@ -62,8 +158,7 @@ impl UserRepo {
             .bind(provider)
             .bind(&provider_user_id)
             .execute(&mut *tx)
-            .await
-            .map_err(|_| poem::Error::from_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR))?;
+            .await?;
 
             // maybe update display_name on users
             sqlx::query(
@@ -78,8 +173,7 @@ impl UserRepo {
             .bind(&display_name)
             .bind(row.user_id)
             .execute(&mut *tx)
-            .await
-            .map_err(|_| poem::Error::from_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR))?;
+            .await?;
 
             row.user_id
         } else {
@@ -93,8 +187,7 @@ impl UserRepo {
             )
             .bind(&display_name)
             .fetch_one(&mut *tx)
-            .await
-            .map_err(|_| poem::Error::from_status(poem::http::StatusCode::INTERNAL_SERVER_ERROR))?;
+            .await?;
 
             let new_user_id = rec.0;
 
@@ -112,20 +205,66 @@ impl UserRepo {
             .bind(new_user_id)
             .bind(provider)
             .bind(&provider_user_id)
-            .bind(&token.access_token)
-            .bind(&token.refresh_token)
+            .bind(token.access_token.expose())
+            .bind(token.refresh_token.as_ref().map(Secret::expose))
             .bind(token.expires_at)
             .execute(&mut *tx)
-            .await.map_err(|_| poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR))?;
+            .await?;
 
             new_user_id
         };
 
-        tx.commit()
-            .await
-            .map_err(|_| poem::Error::from_status(StatusCode::INTERNAL_SERVER_ERROR))?;
+        tx.commit().await?;
 
         // 4: return user_id
-        Ok(user_id)
+        Ok(Some(user_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inat_user() -> InatUser {
+        InatUser {
+            id: 42,
+            login: "naturalist".to_string(),
+            name: None,
+            icon_url: None,
+            email: None,
+        }
+    }
+
+    fn token() -> TokenWithExpiry {
+        TokenWithExpiry {
+            access_token: Secret::new("access".to_string()),
+            refresh_token: None,
+            expires_at: None,
+        }
+    }
+
+    #[sqlx::test]
+    async fn deleted_users_cannot_sign_in_again(pool: PgPool) {
+        let repo = UserRepo::new(pool);
+        let user_id = repo
+            .upsert_inat_user(&inat_user(), &token())
+            .await
+            .unwrap()
+            .expect("new user signs in");
+        assert_eq!(
+            repo.upsert_inat_user(&inat_user(), &token()).await.unwrap(),
+            Some(user_id)
+        );
+
+        assert!(repo.soft_delete_user(user_id).await.unwrap());
+        assert!(!repo.soft_delete_user(user_id).await.unwrap());
+        assert_eq!(
+            repo.upsert_inat_user(&inat_user(), &token()).await.unwrap(),
+            None
+        );
+
+        assert_eq!(repo.list_purgeable_users(0).await.unwrap(), [user_id]);
+        assert!(repo.list_purgeable_users(30).await.unwrap().is_empty());
+        assert_eq!(repo.purge_users(&[user_id]).await.unwrap(), 1);
     }
 }
@@ -0,0 +1,103 @@
+use std::time::Instant;
+
+use poem::http::HeaderValue;
+use poem::{Endpoint, IntoResponse, Middleware, PathPattern, Request, Response, Result};
+use tracing::{Instrument, field::Empty, info, info_span};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest client-supplied request id that is propagated as-is
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Id of the request being handled by the current task, if any
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Assigns each request an id (reusing a valid incoming `X-Request-Id`),
+/// returns it in the response headers, and runs the request inside a span
+/// carrying the method, route, user id and status.
+pub struct RequestContext;
+
+impl<E: Endpoint> Middleware<E> for RequestContext {
+    type Output = RequestContextEndpoint<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        RequestContextEndpoint { inner: ep }
+    }
+}
+
+pub struct RequestContextEndpoint<E> {
+    inner: E,
+}
+
+impl<E: Endpoint> Endpoint for RequestContextEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, req: Request) -> Result<Self::Output> {
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| is_valid_request_id(v))
+            .map(str::to_string)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        let span = info_span!(
+            "request",
+            request_id = %request_id,
+            method = %req.method(),
+            path = %req.uri().path(),
+            route = Empty,
+            user_id = Empty,
+            status = Empty,
+        );
+        #[cfg(feature = "otel")]
+        crate::telemetry::otel::set_parent_from_headers(&span, req.headers());
+
+        let start = Instant::now();
+        let fut = async {
+            let result = self.inner.call(req).await.map(IntoResponse::into_response);
+
+            let span = tracing::Span::current();
+            let status = match &result {
+                Ok(resp) => {
+                    if let Some(route) = resp.data::<PathPattern>().filter(|p| !p.0.is_empty()) {
+                        span.record("route", &*route.0);
+                    }
+                    resp.status()
+                }
+                Err(err) => err.status(),
+            };
+            span.record("status", status.as_u16());
+            info!(
+                latency_ms = start.elapsed().as_millis() as u64,
+                "request completed"
+            );
+
+            result
+        };
+
+        let mut result = REQUEST_ID
+            .scope(request_id.clone(), fut.instrument(span))
+            .await;
+
+        if let (Ok(resp), Ok(value)) = (&mut result, HeaderValue::from_str(&request_id)) {
+            resp.headers_mut().insert(REQUEST_ID_HEADER, value);
+        }
+        result
+    }
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
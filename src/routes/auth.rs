@@ -1,5 +1,5 @@
 use crate::{
-    internal_error,
+    error::{ApiError, ApiResult},
     services::{self, auth::UserRow},
 };
 use poem::{
@@ -14,7 +14,8 @@ use poem_openapi::{
 use rand::{Rng, distr::Alphanumeric};
 use serde::Serialize;
 
-use crate::clients::inat::InatClient;
+use crate::http_cache::cache_private;
+use crate::metrics::METRICS;
 use crate::repos::user_repo::UserRepo;
 use crate::session_store::SessionStore;
 use crate::state::AppState;
@@ -26,8 +27,8 @@ pub struct AuthApi {
 #[OpenApi(prefix_path = "/auth")]
 impl AuthApi {
     #[oai(path = "/login-url", method = "get")]
-    async fn login_url(&self) -> poem::Result<Json<LoginUrlResponse>> {
-        let session_repo = SessionStore::new(self.state.redis.clone());
+    async fn login_url(&self) -> ApiResult<Json<LoginUrlResponse>> {
+        let session_repo = SessionStore::new(self.state.redis.clone(), &self.state.config);
         let cfg = &self.state.config;
 
         // Generate random state
@@ -37,11 +38,8 @@ impl AuthApi {
             .map(char::from)
             .collect();
 
-        // Store state in Redis with short TTL (e.g. 10 minutes)
-        session_repo
-            .store_oauth_state(&state)
-            .await
-            .map_err(|e| internal_error("store_oauth_state failed", e))?;
+        // Store state in Redis with a short TTL (`OAUTH_STATE_TTL_SECS`)
+        session_repo.store_oauth_state(&state).await?;
 
         let redirect = urlencoding::encode(&cfg.inat_redirect_uri);
 
@@ -67,50 +65,40 @@ impl AuthApi {
         jar: &CookieJar,
         code: Query<String>,
         state: Query<String>,
-    ) -> poem::Result<payload::Response<()>> {
+    ) -> ApiResult<payload::Response<()>> {
         let cfg = &self.state.config;
-        let session_repo = SessionStore::new(self.state.redis.clone());
+        let session_repo = SessionStore::new(self.state.redis.clone(), &self.state.config);
 
         // 1: Validate state (consume only)
-        let exists = session_repo
-            .consume_oauth_state(&state)
-            .await
-            .map_err(|e| internal_error("consume_oauth_state failed", e))?;
+        let exists = session_repo.consume_oauth_state(&state).await?;
         if !exists {
-            return Err(poem::Error::from_status(StatusCode::BAD_REQUEST));
+            return Err(ApiError::InvalidOAuthState);
         }
 
         // 2: get OAuth token
-        let inat_client = InatClient::new();
-        let token_with_exp = inat_client
-            .exchange_code_for_token(&self.state.config, &code)
-            .await
-            .map_err(|e| internal_error("exchange_code_for_token failed", e))?;
+        let inat_client = &self.state.inat;
+        let token_with_exp = inat_client.exchange_code_for_token(&code).await?;
 
         // 3: get JWT api_token
         let api_token = inat_client
-            .exchange_access_for_api_token(&self.state.config, &token_with_exp.access_token)
-            .await
-            .map_err(|e| internal_error("exchange_access_for_api_token failed", e))?;
+            .exchange_access_for_api_token(&token_with_exp.access_token)
+            .await?;
 
         // 4: get iNat user profile
-        let inat_user = inat_client
-            .fetch_current_user(&api_token)
-            .await
-            .map_err(|e| internal_error("fetch_current_user failed", e))?;
+        let inat_user = inat_client.fetch_current_user(&api_token).await?;
 
         // 5: Upsert user + auth_identity
         let auth = UserRepo::new(self.state.db.clone());
         let user_id = auth
             .upsert_inat_user(&inat_user, &token_with_exp)
-            .await
-            .map_err(|e| internal_error("upsert_inat_user failed", e))?;
+            .await?
+            .ok_or_else(|| ApiError::Forbidden("this account has been deleted".to_string()))?;
+        tracing::Span::current().record("user_id", user_id);
 
         // 6: Create session in Redis
-        let session_id = session_repo
-            .create_session(user_id)
-            .await
-            .map_err(|e| internal_error("create_session failed", e))?;
+        let session_id = session_repo.create_session(user_id).await?;
+
+        METRICS.logins.inc();
 
         // 7: Set cookie and redirect to frontend
         let mut cookie = Cookie::new("taxonia_session", session_id);
@@ -129,8 +117,8 @@ impl AuthApi {
     }
 
     /// Get current logged-in user
-    #[oai(path = "/me", method = "get")]
-    async fn me(&self, jar: &CookieJar) -> poem::Result<Json<MeResponse>> {
+    #[oai(path = "/me", method = "get", transform = "cache_private")]
+    async fn me(&self, jar: &CookieJar) -> ApiResult<Json<MeResponse>> {
         let user = services::auth::get_current_user(&self.state, jar).await?;
         Ok(Json(MeResponse::from(user)))
     }
@@ -1,43 +1,212 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
 use poem_openapi::payload::Json;
-use poem_openapi::{Object, OpenApi};
+use poem_openapi::{ApiResponse, Enum, Object, OpenApi};
 use redis::AsyncCommands;
 use serde::Serialize;
 use tokio::join;
+use tracing::warn;
 
+use crate::error::ApiResult;
 use crate::state::AppState;
 
 pub struct HealthCheckApi {
     pub state: AppState,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Enum)]
+#[oai(rename_all = "snake_case")]
+pub enum ServerStatus {
+    Ok,
+    /// Shutting down; no new requests should be routed here
+    Draining,
+    /// A required dependency is down or too slow
+    Unavailable,
+}
+
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct DependencyCheck {
+    /// Dependency name, e.g. "postgres"
+    name: String,
+    ok: bool,
+    /// Whether a failure of this dependency makes the server unready
+    required: bool,
+    /// Time the check took, in milliseconds
+    latency_ms: u64,
+    /// Reason for the failure, if any
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct ReadinessResponse {
+    status: ServerStatus,
+    checks: Vec<DependencyCheck>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Object)]
+pub struct LivenessResponse {
+    status: ServerStatus,
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Object)]
 pub struct HealthCheckResponse {
+    status: ServerStatus,
     db_ok: bool,
     redis_ok: bool,
     server_ok: bool,
 }
 
+#[derive(ApiResponse)]
+pub enum ReadinessResult {
+    #[oai(status = 200)]
+    Ready(Json<ReadinessResponse>),
+    /// Draining, or a required dependency is unavailable
+    #[oai(status = 503)]
+    NotReady(Json<ReadinessResponse>),
+}
+
+#[derive(ApiResponse)]
+pub enum HealthCheckResult {
+    #[oai(status = 200)]
+    Ok(Json<HealthCheckResponse>),
+    /// Draining, or a dependency is unavailable
+    #[oai(status = 503)]
+    Unavailable(Json<HealthCheckResponse>),
+}
+
 #[OpenApi]
 impl HealthCheckApi {
-    /// Health check endpoint
-    #[oai(path = "/health_check", method = "get", operation_id = "health_check")]
-    async fn health_check(&self) -> poem::Result<Json<HealthCheckResponse>> {
-        // TODO: Add timeout handling for the DB and Redis checks independently
-        let db_check = sqlx::query("SELECT 1").execute(&self.state.db);
-
-        let redis_check = async {
-            let mut conn = self.state.redis.get_multiplexed_async_connection().await?;
-            let _ = conn.ping::<String>().await?;
-            Ok::<(), redis::RedisError>(())
+    /// Liveness probe: the process is up and serving requests. Does not
+    /// touch any dependency.
+    #[oai(path = "/health/live", method = "get", operation_id = "health_live")]
+    async fn live(&self) -> ApiResult<Json<LivenessResponse>> {
+        let status = if self.state.shutdown.is_draining() {
+            ServerStatus::Draining
+        } else {
+            ServerStatus::Ok
         };
+        Ok(Json(LivenessResponse { status }))
+    }
+
+    /// Readiness probe: the server can handle traffic. Each dependency is
+    /// checked concurrently with its own timeout.
+    #[oai(path = "/health/ready", method = "get", operation_id = "health_ready")]
+    async fn ready(&self) -> ApiResult<ReadinessResult> {
+        let resp = self.readiness().await;
+        if resp.status == ServerStatus::Ok {
+            Ok(ReadinessResult::Ready(Json(resp)))
+        } else {
+            Ok(ReadinessResult::NotReady(Json(resp)))
+        }
+    }
+
+    /// Health check endpoint. Deprecated in favour of `/health/ready`.
+    #[oai(
+        path = "/health_check",
+        method = "get",
+        operation_id = "health_check",
+        deprecated
+    )]
+    async fn health_check(&self) -> ApiResult<HealthCheckResult> {
+        let resp = self.readiness().await;
+        let check_ok = |name: &str| resp.checks.iter().any(|c| c.name == name && c.ok);
+        let body = HealthCheckResponse {
+            status: resp.status,
+            db_ok: check_ok("postgres"),
+            redis_ok: check_ok("redis"),
+            server_ok: resp.status != ServerStatus::Draining,
+        };
+
+        if resp.status == ServerStatus::Ok {
+            Ok(HealthCheckResult::Ok(Json(body)))
+        } else {
+            Ok(HealthCheckResult::Unavailable(Json(body)))
+        }
+    }
+}
+
+impl HealthCheckApi {
+    async fn readiness(&self) -> ReadinessResponse {
+        let cfg = &self.state.config;
+
+        let db_check = run_check(
+            "postgres",
+            true,
+            Duration::from_millis(cfg.health_db_timeout_ms),
+            async {
+                sqlx::query("SELECT 1").execute(&self.state.db).await?;
+                Ok::<(), sqlx::Error>(())
+            },
+        );
+
+        let redis_check = run_check(
+            "redis",
+            true,
+            Duration::from_millis(cfg.health_redis_timeout_ms),
+            async {
+                let mut conn = self.state.redis.get_multiplexed_async_connection().await?;
+                let _ = conn.ping::<String>().await?;
+                Ok::<(), redis::RedisError>(())
+            },
+        );
+
+        let inat_check = async {
+            if !cfg.health_check_inat {
+                return None;
+            }
+            let check = run_check(
+                "inaturalist",
+                false,
+                Duration::from_millis(cfg.health_inat_timeout_ms),
+                self.state.inat.check_reachable(),
+            );
+            Some(check.await)
+        };
+
+        let (db, redis, inat) = join!(db_check, redis_check, inat_check);
+        let checks: Vec<_> = [Some(db), Some(redis), inat]
+            .into_iter()
+            .flatten()
+            .collect();
+
+        let status = if self.state.shutdown.is_draining() {
+            ServerStatus::Draining
+        } else if checks.iter().any(|c| c.required && !c.ok) {
+            ServerStatus::Unavailable
+        } else {
+            ServerStatus::Ok
+        };
+
+        ReadinessResponse { status, checks }
+    }
+}
+
+async fn run_check<E: std::fmt::Display>(
+    name: &str,
+    required: bool,
+    timeout: Duration,
+    check: impl Future<Output = Result<(), E>>,
+) -> DependencyCheck {
+    let start = Instant::now();
+    let result = tokio::time::timeout(timeout, check).await;
+    let latency_ms = start.elapsed().as_millis() as u64;
 
-        let (db_res, redis_res) = join!(db_check, redis_check);
-        let (db_ok, redis_ok) = (db_res.is_ok(), redis_res.is_ok());
+    let error = match result {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => {
+            // details may name internal hosts, so only log them
+            warn!(dependency = name, "health check failed: {e}");
+            Some("unavailable".to_string())
+        }
+        Err(_) => Some(format!("timed out after {}ms", timeout.as_millis())),
+    };
 
-        Ok(Json(HealthCheckResponse {
-            db_ok,
-            redis_ok,
-            server_ok: true,
-        }))
+    DependencyCheck {
+        name: name.to_string(),
+        ok: error.is_none(),
+        required,
+        latency_ms,
+        error,
     }
 }
@@ -1,3 +1,61 @@
+use poem_openapi::OpenApi;
+
+use crate::state::AppState;
+
 pub mod auth;
 pub mod health_check;
+pub mod observations;
+pub mod places;
 pub mod quiz;
+pub mod taxa;
+
+/// Operations served under `/v1`.
+///
+/// Each API version is its own `OpenApiService` mounted under its own
+/// prefix. A `/v2` gets a sibling function that reuses the unchanged APIs from
+/// here and swaps in the ones whose shapes changed, so both can be served
+/// side by side until `/v1` is sunset.
+pub fn v1(state: &AppState) -> impl OpenApi + use<> {
+    (
+        health_check::HealthCheckApi {
+            state: state.clone(),
+        },
+        auth::AuthApi {
+            state: state.clone(),
+        },
+        quiz::QuizResultsApi {
+            state: state.clone(),
+        },
+        quiz::QuizApi {
+            state: state.clone(),
+        },
+        taxa::TaxaApi {
+            state: state.clone(),
+        },
+        observations::ObservationsApi {
+            state: state.clone(),
+        },
+        places::PlacesApi {
+            state: state.clone(),
+        },
+    )
+}
+
+/// Operations still served at their original unversioned paths until the
+/// sunset date: the ones that existed before `/v1`. New operations only go
+/// into the versioned sets, but these share their implementation with `/v1`
+/// and follow its changes, which must stay backwards compatible (e.g. new
+/// optional request fields and new response fields) while this is mounted.
+pub fn legacy(state: &AppState) -> impl OpenApi + use<> {
+    (
+        health_check::HealthCheckApi {
+            state: state.clone(),
+        },
+        auth::AuthApi {
+            state: state.clone(),
+        },
+        quiz::QuizResultsApi {
+            state: state.clone(),
+        },
+    )
+}
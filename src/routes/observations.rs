@@ -0,0 +1,247 @@
+use chrono::NaiveDate;
+use poem::web::cookie::CookieJar;
+use poem_openapi::{Object, OpenApi, param::Query, payload::Json};
+
+use crate::clients::inat::{InatObservation, InatPhoto, ObservationFilter};
+use crate::error::{ApiError, ApiResult};
+use crate::license::{License, REUSABLE};
+use crate::routes::taxa::validate_locale;
+use crate::services::auth::get_optional_user;
+use crate::services::observations;
+use crate::state::AppState;
+
+pub struct ObservationsApi {
+    pub state: AppState,
+}
+
+/// A research-grade observation, for photo identification
+#[derive(Object, Debug, Clone)]
+pub struct Observation {
+    /// iNaturalist observation id
+    pub id: i64,
+    /// Link to the observation on iNaturalist
+    pub url: Option<String>,
+    /// Date the organism was observed, e.g. "2024-05-01"
+    pub observed_on: Option<String>,
+    /// Rough, human-readable location
+    pub place_guess: Option<String>,
+    /// The community identification
+    pub taxon: ObservationTaxon,
+    /// Photos in the order the observer added them
+    pub photos: Vec<ObservationPhoto>,
+    /// iNaturalist login of the observer
+    pub observer: Option<String>,
+    /// Normalised licence of the observation data, separate from its
+    /// photos', e.g. "cc-by-nc" or "all-rights-reserved"
+    pub license_code: String,
+}
+
+#[derive(Object, Debug, Clone)]
+pub struct ObservationTaxon {
+    pub id: i64,
+    /// Scientific name
+    pub name: String,
+    pub rank: Option<String>,
+    /// Common name in the requested locale, if there is one
+    pub common_name: Option<String>,
+    /// Broad group used for icons, e.g. "Aves", "Plantae"
+    pub iconic_taxon: Option<String>,
+    /// Ids from the root of the tree of life down to the parent
+    pub ancestor_ids: Vec<i64>,
+}
+
+#[derive(Object, Debug, Clone)]
+pub struct ObservationPhoto {
+    pub id: i64,
+    /// 75px square thumbnail
+    pub square_url: Option<String>,
+    /// Up to 500px on the longest side
+    pub medium_url: Option<String>,
+    /// Up to 1024px on the longest side
+    pub large_url: Option<String>,
+    /// Credit line to display with the photo
+    pub attribution: String,
+    /// Normalised licence code, e.g. "cc-by-nc" or "all-rights-reserved"
+    pub license_code: String,
+    /// e.g. "CC BY-NC"
+    pub license_name: String,
+    /// Licence text; absent for all rights reserved
+    pub license_url: Option<String>,
+}
+
+#[derive(Object)]
+struct ObservationSampleResponse {
+    /// Fewer than `count` when not enough observations match
+    items: Vec<Observation>,
+}
+
+#[OpenApi(prefix_path = "/observations")]
+impl ObservationsApi {
+    /// Pick random research-grade observations with photos. Signed-in users
+    /// don't get observations they've already been shown.
+    #[oai(path = "/sample", method = "get")]
+    #[allow(clippy::too_many_arguments)]
+    async fn sample(
+        &self,
+        jar: &CookieJar,
+        /// Only observations of this taxon or its descendants
+        taxon_id: Query<Option<i64>>,
+        /// Only observations within this iNaturalist place
+        place_id: Query<Option<i64>>,
+        /// Observed on or after this date
+        d1: Query<Option<NaiveDate>>,
+        /// Observed on or before this date
+        d2: Query<Option<NaiveDate>>,
+        /// Observed in this month of any year, 1-12
+        month: Query<Option<u32>>,
+        /// Locale for common names, e.g. "en"
+        locale: Query<Option<String>>,
+        #[oai(default = "default_count")] count: Query<u32>,
+        /// Also return observations the user has already been shown
+        #[oai(default)]
+        include_seen: Query<bool>,
+        /// Only return photos under these licences, e.g. "cc0", "cc-by".
+        /// Repeat the parameter for several.
+        #[oai(default)]
+        photo_license: Query<Vec<String>>,
+        /// Only return photos that may be reused with attribution, i.e. not
+        /// "all-rights-reserved". Combined with `photo_license` if both are
+        /// given.
+        #[oai(default)]
+        reusable_only: Query<bool>,
+    ) -> ApiResult<Json<ObservationSampleResponse>> {
+        if month.0.is_some_and(|m| !(1..=12).contains(&m)) {
+            return Err(ApiError::Validation(
+                "month must be between 1 and 12".to_string(),
+            ));
+        }
+        if let (Some(d1), Some(d2)) = (d1.0, d2.0)
+            && d1 > d2
+        {
+            return Err(ApiError::Validation("d1 must not be after d2".to_string()));
+        }
+        let locale = validate_locale(locale.0)?;
+        let photo_licenses = photo_licenses(photo_license.0, reusable_only.0)?;
+
+        let filter = ObservationFilter {
+            taxon_ids: taxon_id.0.into_iter().collect(),
+            place_id: place_id.0,
+            d1: d1.0,
+            d2: d2.0,
+            month: month.0,
+            photo_licenses,
+        };
+        let user = get_optional_user(&self.state, jar).await?;
+
+        let sampled = observations::sample(
+            &self.state,
+            user.map(|u| u.id),
+            &filter,
+            locale.as_deref(),
+            count.0.clamp(1, 30) as usize,
+            include_seen.0,
+        )
+        .await?;
+
+        Ok(Json(ObservationSampleResponse {
+            items: sampled
+                .into_iter()
+                .filter_map(Observation::from_inat)
+                .collect(),
+        }))
+    }
+}
+
+fn default_count() -> u32 {
+    10
+}
+
+/// The licences photos are restricted to; empty for any
+fn photo_licenses(codes: Vec<String>, reusable_only: bool) -> ApiResult<Vec<License>> {
+    let mut licenses = codes
+        .iter()
+        .flat_map(|c| c.split(','))
+        .map(|code| {
+            code.parse::<License>().map_err(|_| {
+                ApiError::Validation(format!("unknown photo_license \"{}\"", code.trim()))
+            })
+        })
+        .collect::<ApiResult<Vec<_>>>()?;
+
+    if reusable_only {
+        if licenses.is_empty() {
+            licenses = REUSABLE.to_vec();
+        } else {
+            licenses.retain(|l| l.is_reusable());
+            if licenses.is_empty() {
+                return Err(ApiError::Validation(
+                    "photo_license has no reusable licences".to_string(),
+                ));
+            }
+        }
+    }
+    Ok(licenses)
+}
+
+impl Observation {
+    /// `None` for observations without a taxon
+    fn from_inat(o: InatObservation) -> Option<Self> {
+        let taxon = o.taxon?;
+        // credited in attributions iNaturalist didn't provide
+        let owner = o
+            .user
+            .as_ref()
+            .map(|u| u.name.clone().unwrap_or(u.login.clone()));
+        Some(Self {
+            id: o.id,
+            url: o.uri,
+            observed_on: o.observed_on,
+            place_guess: o.place_guess,
+            taxon: ObservationTaxon {
+                ancestor_ids: taxon.ancestor_ids(),
+                id: taxon.id,
+                name: taxon.name,
+                rank: taxon.rank,
+                common_name: taxon.preferred_common_name,
+                iconic_taxon: taxon.iconic_taxon_name,
+            },
+            photos: o
+                .photos
+                .into_iter()
+                .map(|p| ObservationPhoto::new(p, owner.as_deref()))
+                .collect(),
+            observer: o.user.map(|u| u.login),
+            license_code: License::from_inat(o.license_code.as_deref())
+                .code()
+                .to_string(),
+        })
+    }
+}
+
+impl ObservationPhoto {
+    /// `owner` is credited when iNaturalist didn't provide an attribution
+    pub(crate) fn new(p: InatPhoto, owner: Option<&str>) -> Self {
+        let license = License::from_inat(p.license_code.as_deref());
+        let square_url = p.square_url.or(p.url);
+        Self {
+            id: p.id,
+            medium_url: p
+                .medium_url
+                .or_else(|| resized(square_url.as_deref()?, "medium")),
+            large_url: square_url.as_deref().and_then(|url| resized(url, "large")),
+            square_url,
+            attribution: license.attribution(p.attribution.as_deref(), owner),
+            license_code: license.code().to_string(),
+            license_name: license.name().to_string(),
+            license_url: license.url().map(str::to_string),
+        }
+    }
+}
+
+/// iNaturalist photo URLs differ only in the size, e.g.
+/// `.../photos/123/square.jpg` and `.../photos/123/medium.jpg`
+fn resized(square_url: &str, size: &str) -> Option<String> {
+    square_url
+        .contains("/square.")
+        .then(|| square_url.replacen("/square.", &format!("/{size}."), 1))
+}
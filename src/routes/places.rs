@@ -0,0 +1,216 @@
+use poem_openapi::{
+    Object, OpenApi,
+    param::{Path, Query},
+    payload::Json,
+};
+
+use crate::clients::inat::{InatPlace, SpeciesCountFilter};
+use crate::error::{ApiError, ApiResult};
+use crate::http_cache::cache_public;
+use crate::routes::taxa::{Taxon, validate_locale};
+use crate::state::AppState;
+
+/// Values iNaturalist accepts for `iconic_taxa`
+const ICONIC_TAXA: &[&str] = &[
+    "Actinopterygii",
+    "Amphibia",
+    "Animalia",
+    "Arachnida",
+    "Aves",
+    "Chromista",
+    "Fungi",
+    "Insecta",
+    "Mammalia",
+    "Mollusca",
+    "Plantae",
+    "Protozoa",
+    "Reptilia",
+];
+
+pub struct PlacesApi {
+    pub state: AppState,
+}
+
+/// A place on iNaturalist, such as a country, county or park
+#[derive(Object, Debug, Clone)]
+pub struct Place {
+    /// iNaturalist place id
+    pub id: i64,
+    pub name: String,
+    /// Name qualified with its country or state, e.g. "Travis County, TX, US"
+    pub display_name: String,
+    /// 0 for countries, 10 for states, 20 for counties; absent for
+    /// user-defined places such as parks
+    pub admin_level: Option<i64>,
+    /// Ids of the places containing it, largest first
+    pub ancestor_place_ids: Vec<i64>,
+    /// Centre point
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+#[derive(Object, Debug, Clone)]
+pub struct SpeciesCount {
+    /// Research-grade observations of the species in the place
+    pub count: i64,
+    pub taxon: Taxon,
+}
+
+#[derive(Object)]
+struct PlaceSearchResponse {
+    items: Vec<Place>,
+}
+
+#[derive(Object)]
+struct PlaceSpeciesResponse {
+    /// Species matching the filters, across all pages
+    total: i64,
+    /// Most observed first
+    items: Vec<SpeciesCount>,
+}
+
+#[OpenApi(prefix_path = "/places")]
+impl PlacesApi {
+    /// Search places by name, for autocomplete
+    #[oai(path = "/search", method = "get", transform = "cache_public")]
+    async fn search(
+        &self,
+        /// Part of a place name
+        q: Query<String>,
+        #[oai(default = "default_limit")] limit: Query<u32>,
+    ) -> ApiResult<Json<PlaceSearchResponse>> {
+        let q = q.0.trim();
+        if q.is_empty() || q.len() > 100 {
+            return Err(ApiError::Validation(
+                "q must be between 1 and 100 characters".to_string(),
+            ));
+        }
+
+        let places = self
+            .state
+            .inat
+            .autocomplete_places(q, limit.0.clamp(1, 30))
+            .await?;
+
+        Ok(Json(PlaceSearchResponse {
+            items: places.into_iter().map(Place::from).collect(),
+        }))
+    }
+
+    /// List the species observed in a place, most observed first, e.g. for
+    /// "birds of my county" quizzes. Only research-grade observations count.
+    #[oai(path = "/:id/species", method = "get", transform = "cache_public")]
+    #[allow(clippy::too_many_arguments)]
+    async fn species(
+        &self,
+        id: Path<i64>,
+        /// Only species in this broad group, e.g. "Aves", "Plantae"
+        iconic_taxon: Query<Option<String>>,
+        /// Only species below this taxon
+        taxon_id: Query<Option<i64>>,
+        /// Only observations made in this month of any year, 1-12
+        month: Query<Option<u32>>,
+        /// Locale for common names, e.g. "en"
+        locale: Query<Option<String>>,
+        #[oai(default = "default_page_size")] limit: Query<u32>,
+        /// Starts at 1
+        #[oai(default = "default_page")]
+        page: Query<u32>,
+    ) -> ApiResult<Json<PlaceSpeciesResponse>> {
+        if month.0.is_some_and(|m| !(1..=12).contains(&m)) {
+            return Err(ApiError::Validation(
+                "month must be between 1 and 12".to_string(),
+            ));
+        }
+        let iconic_taxon = match iconic_taxon.0 {
+            Some(name) => Some(
+                ICONIC_TAXA
+                    .iter()
+                    .find(|t| t.eq_ignore_ascii_case(name.trim()))
+                    .map(|t| t.to_string())
+                    .ok_or_else(|| {
+                        ApiError::Validation(format!(
+                            "iconic_taxon must be one of {}",
+                            ICONIC_TAXA.join(", ")
+                        ))
+                    })?,
+            ),
+            None => None,
+        };
+        let locale = validate_locale(locale.0)?;
+
+        self.state
+            .inat
+            .fetch_place(id.0)
+            .await?
+            .ok_or_else(|| ApiError::NotFound("place".to_string()))?;
+
+        let filter = SpeciesCountFilter {
+            iconic_taxon,
+            taxon_id: taxon_id.0,
+            month: month.0,
+        };
+        let counts = self
+            .state
+            .inat
+            .species_counts(
+                Some(id.0),
+                &filter,
+                locale.as_deref(),
+                limit.0.clamp(1, 500),
+                page.0.max(1),
+            )
+            .await?;
+
+        Ok(Json(PlaceSpeciesResponse {
+            total: counts.total,
+            items: counts
+                .results
+                .into_iter()
+                .map(|c| SpeciesCount {
+                    count: c.count,
+                    taxon: Taxon::from(c.taxon),
+                })
+                .collect(),
+        }))
+    }
+}
+
+fn default_limit() -> u32 {
+    10
+}
+
+fn default_page_size() -> u32 {
+    50
+}
+
+fn default_page() -> u32 {
+    1
+}
+
+impl From<InatPlace> for Place {
+    fn from(p: InatPlace) -> Self {
+        let (latitude, longitude) = p
+            .location
+            .as_deref()
+            .and_then(|l| l.split_once(','))
+            .and_then(|(lat, lng)| Some((lat.trim().parse().ok()?, lng.trim().parse().ok()?)))
+            .unzip();
+        let ancestor_place_ids = p
+            .ancestor_place_ids
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|&id| id != p.id)
+            .collect();
+
+        Self {
+            display_name: p.display_name.unwrap_or_else(|| p.name.clone()),
+            id: p.id,
+            name: p.name,
+            admin_level: p.admin_level,
+            ancestor_place_ids,
+            latitude,
+            longitude,
+        }
+    }
+}
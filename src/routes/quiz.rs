@@ -1,13 +1,32 @@
 use chrono::{DateTime, Utc};
 use poem::web::cookie::CookieJar;
-use poem_openapi::{Object, OpenApi, param::Query, payload::Json};
+use poem_openapi::{
+    Object, OpenApi,
+    param::{Path, Query},
+    payload::Json,
+};
 use serde_json::Value;
 
-use crate::internal_error;
-use crate::repos::quiz_repo::{QuizRepo, QuizResultRow};
-use crate::services::auth::get_current_user;
+use crate::error::{ApiError, ApiResult};
+use crate::http_cache::cache_private;
+use crate::metrics::METRICS;
+use crate::repos::quiz_repo::{QuizAnswerRow, QuizChoice, QuizRepo, QuizResultRow};
+use crate::routes::observations::ObservationPhoto;
+use crate::routes::taxa::validate_locale;
+use crate::services::auth::{get_current_user, get_optional_user};
+use crate::services::quiz::{self, Difficulty, QuizParams, QuizSession, RANKS};
 use crate::state::AppState;
 
+/// Most answers accepted with a client-reported result
+const MAX_SAVED_ANSWERS: usize = 200;
+
+/// Quiz results reported by clients, the operations that predate `/v1`
+#[derive(Clone)]
+pub struct QuizResultsApi {
+    pub state: AppState,
+}
+
+/// Quizzes generated and scored by the server
 #[derive(Clone)]
 pub struct QuizApi {
     pub state: AppState,
@@ -19,12 +38,31 @@ struct SaveQuizResultRequest {
     quiz_type: String,
     /// Arbitrary quiz configuration (taxon filters, place, etc.)
     params: Value,
-    /// Final score from 0.0 to 1.0
+    /// Final score from 0.0 to 1.0. Ignored when `answers` are given, as the
+    /// score is then the fraction answered correctly
     score: f64,
-    /// Optional total number of questions
+    /// Optional total number of questions. Ignored when `answers` are given,
+    /// as every question is then one answer
     question_count: Option<i32>,
     /// Optional duration in seconds
     duration_seconds: Option<i32>,
+    /// Optional per-question answers, in the order they were asked, skipped
+    /// questions included
+    answers: Option<Vec<SaveQuizAnswerRequest>>,
+}
+
+#[derive(Object, Debug)]
+struct SaveQuizAnswerRequest {
+    /// The correct taxon
+    taxon_id: i64,
+    /// The taxon the user chose; absent if the question was skipped
+    chosen_taxon_id: Option<i64>,
+    /// Time taken to answer, in milliseconds
+    response_ms: Option<i32>,
+    /// iNaturalist observation the question was about
+    observation_id: Option<i64>,
+    /// iNaturalist photo shown for the question
+    photo_id: Option<i64>,
 }
 
 #[derive(Object, Debug)]
@@ -40,30 +78,168 @@ struct QuizResultResponse {
     score: f64,
     question_count: Option<i32>,
     duration_seconds: Option<i32>,
+    /// True when the score was computed by the server from a quiz session,
+    /// false when it was reported by the client
+    verified: bool,
     created_at: DateTime<Utc>,
 }
 
+#[derive(Object, Debug)]
+struct QuizResultDetailResponse {
+    #[oai(flatten)]
+    result: QuizResultResponse,
+    /// Empty for results saved without answers
+    answers: Vec<QuizAnswer>,
+}
+
+#[derive(Object, Debug)]
+struct QuizAnswer {
+    /// 0-based position in the quiz
+    position: i32,
+    /// The correct taxon
+    taxon_id: i64,
+    /// Absent if the question was skipped
+    chosen_taxon_id: Option<i64>,
+    correct: bool,
+    response_ms: Option<i32>,
+    observation_id: Option<i64>,
+    photo_id: Option<i64>,
+}
+
 #[derive(Object)]
 struct ListQuizResultsResponse {
     items: Vec<QuizResultResponse>,
 }
 
+#[derive(Object, Debug)]
+struct CreateQuizSessionRequest {
+    /// Only ask about taxa below this one, e.g. 3 for birds
+    taxon_id: Option<i64>,
+    /// Only ask about taxa observed in this iNaturalist place
+    place_id: Option<i64>,
+    /// Rank to identify to: "species" (default), "genus", "family", "order"
+    /// or "class"
+    rank: Option<String>,
+    /// Number of questions, 1-20 (default 10). Fewer are returned when not
+    /// enough observations match.
+    question_count: Option<u32>,
+    /// How alike the choices are (default "medium")
+    difficulty: Option<Difficulty>,
+    /// Locale for common names, e.g. "en"
+    locale: Option<String>,
+}
+
+/// A generated quiz. Answers aren't included.
+#[derive(Object, Debug)]
+struct QuizSessionResponse {
+    /// Shareable id; `GET /quiz/sessions/{id}` returns the same questions
+    id: String,
+    taxon_id: Option<i64>,
+    place_id: Option<i64>,
+    rank: String,
+    difficulty: Difficulty,
+    /// Number of questions asked for. `questions` only has fewer when not
+    /// enough observations with reusable photos match the filters.
+    question_count: u32,
+    questions: Vec<QuizQuestion>,
+    /// Position of the next question to answer; absent once completed
+    next_position: Option<i32>,
+    created_at: DateTime<Utc>,
+    completed_at: Option<DateTime<Utc>>,
+    /// Fraction answered correctly, computed by the server on completion
+    score: Option<f64>,
+}
+
+#[derive(Object, Debug)]
+struct QuizQuestion {
+    /// 0-based position in the quiz
+    position: i32,
+    /// Photos of the organism to identify, from one observation
+    photos: Vec<ObservationPhoto>,
+    /// Possible answers, exactly one of them correct
+    choices: Vec<QuizQuestionChoice>,
+}
+
+#[derive(Object, Debug)]
+struct QuizQuestionChoice {
+    taxon_id: i64,
+    /// Scientific name
+    name: String,
+    rank: Option<String>,
+    /// Common name in the quiz's locale, if there is one
+    common_name: Option<String>,
+}
+
+#[derive(Object, Debug)]
+struct SubmitQuizAnswerRequest {
+    /// Position of the question; questions are answered in order
+    position: i32,
+    /// The chosen taxon, one of the question's choices
+    taxon_id: i64,
+}
+
+#[derive(Object, Debug)]
+struct QuizAnswerResponse {
+    position: i32,
+    correct: bool,
+    correct_taxon_id: i64,
+    /// Time taken, measured by the server from the previous answer (or from
+    /// when the quiz was created, for the first question)
+    response_ms: i32,
+    /// True once every question has been answered
+    completed: bool,
+    /// Fraction answered correctly, once completed
+    score: Option<f64>,
+    /// The verified result saved on completion, for signed-in users
+    result_id: Option<i64>,
+}
+
 #[OpenApi(prefix_path = "/quiz")]
-impl QuizApi {
-    // /// Save a completed quiz result for the current user
+impl QuizResultsApi {
+    /// Save a completed quiz result reported by the client. Such results are
+    /// stored as unverified; quizzes played through `/quiz/sessions` are
+    /// scored by the server instead.
     #[oai(path = "/results", method = "post")]
     async fn save_result(
         &self,
         jar: &CookieJar,
         Json(body): Json<SaveQuizResultRequest>,
-    ) -> poem::Result<Json<SaveQuizResultResponse>> {
+    ) -> ApiResult<Json<SaveQuizResultResponse>> {
         // Require login
         let user = get_current_user(&self.state, jar).await?;
 
         let repo = QuizRepo::new(self.state.db.clone());
 
-        // Clamp score to [0,1] just in case
-        let score = body.score.clamp(0.0, 1.0);
+        let answers: Vec<QuizAnswerRow> = body
+            .answers
+            .unwrap_or_default()
+            .into_iter()
+            .enumerate()
+            .map(|(position, a)| QuizAnswerRow {
+                position: position as i32,
+                taxon_id: a.taxon_id,
+                chosen_taxon_id: a.chosen_taxon_id,
+                correct: a.chosen_taxon_id == Some(a.taxon_id),
+                response_ms: a.response_ms.map(|ms| ms.max(0)),
+                observation_id: a.observation_id,
+                photo_id: a.photo_id,
+            })
+            .collect();
+        if answers.len() > MAX_SAVED_ANSWERS {
+            return Err(ApiError::Validation(format!(
+                "at most {MAX_SAVED_ANSWERS} answers can be saved"
+            )));
+        }
+        let (score, question_count) = if answers.is_empty() {
+            // Clamp score to [0,1] just in case
+            (body.score.clamp(0.0, 1.0), body.question_count)
+        } else {
+            let correct = answers.iter().filter(|a| a.correct).count();
+            (
+                correct as f64 / answers.len() as f64,
+                Some(answers.len() as i32),
+            )
+        };
 
         let id = repo
             .insert_quiz_result(
@@ -71,23 +247,25 @@ impl QuizApi {
                 &body.quiz_type,
                 &body.params,
                 score,
-                body.question_count,
+                question_count,
                 body.duration_seconds,
+                &answers,
             )
-            .await
-            .map_err(|e| internal_error("insert_quiz_result failed", e))?;
+            .await?;
+
+        METRICS.quiz_results_saved.inc();
 
         Ok(Json(SaveQuizResultResponse { id }))
     }
 
     /// List recent quiz results for the current user
-    #[oai(path = "/results", method = "get")]
+    #[oai(path = "/results", method = "get", transform = "cache_private")]
     async fn list_results(
         &self,
         jar: &CookieJar,
         #[oai(default = "default_limit")] limit: Query<i64>,
         #[oai(default = "default_offset")] offset: Query<i64>,
-    ) -> poem::Result<Json<ListQuizResultsResponse>> {
+    ) -> ApiResult<Json<ListQuizResultsResponse>> {
         let limit = limit.0.clamp(1, 100);
         let offset = offset.0.max(0);
         let user = get_current_user(&self.state, jar).await?;
@@ -95,23 +273,219 @@ impl QuizApi {
         let repo = QuizRepo::new(self.state.db.clone());
         let rows = repo
             .list_quiz_results_for_user(user.id, limit, offset)
-            .await
-            .map_err(|e| internal_error("list_quiz_results_for_user failed", e))?;
+            .await?;
+
+        let items = rows.into_iter().map(QuizResultResponse::from).collect();
 
-        let items = rows
+        Ok(Json(ListQuizResultsResponse { items }))
+    }
+}
+
+#[OpenApi(prefix_path = "/quiz")]
+impl QuizApi {
+    /// Get one of the current user's quiz results with its per-question
+    /// answers
+    #[oai(path = "/results/:id", method = "get", transform = "cache_private")]
+    async fn get_result(
+        &self,
+        jar: &CookieJar,
+        id: Path<i64>,
+    ) -> ApiResult<Json<QuizResultDetailResponse>> {
+        let user = get_current_user(&self.state, jar).await?;
+
+        let repo = QuizRepo::new(self.state.db.clone());
+        let result = repo
+            .get_quiz_result(user.id, id.0)
+            .await?
+            .ok_or_else(|| ApiError::NotFound("quiz result".to_string()))?;
+        let answers = repo
+            .list_answers(result.id)
+            .await?
             .into_iter()
-            .map(|r| QuizResultResponse {
-                id: r.id,
-                quiz_type: r.quiz_type,
-                params: r.params,
-                score: r.score,
-                question_count: r.question_count,
-                duration_seconds: r.duration_seconds,
-                created_at: r.created_at,
+            .map(QuizAnswer::from)
+            .collect();
+
+        Ok(Json(QuizResultDetailResponse {
+            result: result.into(),
+            answers,
+        }))
+    }
+
+    /// Generate a quiz from iNaturalist observations. Each question has
+    /// photos of one observation and a set of taxa to choose from; the
+    /// answers are kept on the server. Signed-in users don't get
+    /// observations they've already been shown.
+    #[oai(path = "/sessions", method = "post")]
+    async fn create_session(
+        &self,
+        jar: &CookieJar,
+        Json(body): Json<CreateQuizSessionRequest>,
+    ) -> ApiResult<Json<QuizSessionResponse>> {
+        let rank = body.rank.unwrap_or_else(|| "species".to_string());
+        if !RANKS.contains(&rank.as_str()) {
+            return Err(ApiError::Validation(format!(
+                "rank must be one of {}",
+                RANKS.join(", ")
+            )));
+        }
+        let question_count = body.question_count.unwrap_or(10);
+        if !(1..=20).contains(&question_count) {
+            return Err(ApiError::Validation(
+                "question_count must be between 1 and 20".to_string(),
+            ));
+        }
+        let locale = validate_locale(body.locale)?;
+        let user = get_optional_user(&self.state, jar).await?;
+
+        let params = QuizParams {
+            taxon_id: body.taxon_id,
+            place_id: body.place_id,
+            rank,
+            question_count: question_count as usize,
+            difficulty: body.difficulty.unwrap_or_default(),
+            locale,
+        };
+        let session = quiz::create_session(&self.state, user.map(|u| u.id), params).await?;
+
+        Ok(Json(QuizSessionResponse::new(session)?))
+    }
+
+    /// Answer the next question of a quiz. The answer is checked and timed
+    /// by the server; after the last question the quiz is scored and, for a
+    /// signed-in user, saved as a verified result.
+    #[oai(path = "/sessions/:id/answers", method = "post")]
+    async fn submit_answer(
+        &self,
+        jar: &CookieJar,
+        id: Path<String>,
+        Json(body): Json<SubmitQuizAnswerRequest>,
+    ) -> ApiResult<Json<QuizAnswerResponse>> {
+        let user = get_optional_user(&self.state, jar).await?;
+
+        let answer = quiz::answer(
+            &self.state,
+            &id.0,
+            user.map(|u| u.id),
+            body.position,
+            body.taxon_id,
+        )
+        .await?;
+        if answer
+            .completed
+            .as_ref()
+            .is_some_and(|c| c.result_id.is_some())
+        {
+            METRICS.quiz_results_saved.inc();
+        }
+
+        let q = answer.question;
+        Ok(Json(QuizAnswerResponse {
+            position: q.position,
+            correct: q.is_correct.unwrap_or(false),
+            correct_taxon_id: q.correct_taxon_id,
+            response_ms: q.response_ms.unwrap_or_default(),
+            completed: answer.completed.is_some(),
+            score: answer.completed.as_ref().map(|c| c.score),
+            result_id: answer.completed.and_then(|c| c.result_id),
+        }))
+    }
+
+    /// Get a generated quiz by id, without its answers. Changes as questions
+    /// are answered, so it's revalidated rather than cached. Sharing the id
+    /// lets others view the quiz, but a signed-in user's quiz can only be
+    /// answered by them.
+    #[oai(path = "/sessions/:id", method = "get", transform = "cache_private")]
+    async fn get_session(&self, id: Path<String>) -> ApiResult<Json<QuizSessionResponse>> {
+        let session = quiz::get_session(&self.state, &id.0)
+            .await?
+            .ok_or_else(|| ApiError::NotFound("quiz session".to_string()))?;
+
+        Ok(Json(QuizSessionResponse::new(session)?))
+    }
+}
+
+impl QuizSessionResponse {
+    fn new(quiz: QuizSession) -> ApiResult<Self> {
+        let QuizSession { session, questions } = quiz;
+        let params: QuizParams =
+            serde_json::from_value(session.params).map_err(anyhow::Error::from)?;
+
+        let next_position = questions
+            .iter()
+            .find(|q| q.answered_at.is_none())
+            .map(|q| q.position);
+        let questions = questions
+            .into_iter()
+            .map(|q| QuizQuestion {
+                position: q.position,
+                photos: q
+                    .photos
+                    .0
+                    .into_iter()
+                    .map(|p| ObservationPhoto::new(p, q.observer.as_deref()))
+                    .collect(),
+                choices: q
+                    .choices
+                    .0
+                    .into_iter()
+                    .map(QuizQuestionChoice::from)
+                    .collect(),
             })
             .collect();
 
-        Ok(Json(ListQuizResultsResponse { items }))
+        Ok(Self {
+            id: session.id,
+            taxon_id: params.taxon_id,
+            place_id: params.place_id,
+            rank: params.rank,
+            difficulty: params.difficulty,
+            question_count: params.question_count as u32,
+            questions,
+            next_position,
+            created_at: session.created_at,
+            completed_at: session.completed_at,
+            score: session.score,
+        })
+    }
+}
+
+impl From<QuizResultRow> for QuizResultResponse {
+    fn from(r: QuizResultRow) -> Self {
+        Self {
+            id: r.id,
+            quiz_type: r.quiz_type,
+            params: r.params,
+            score: r.score,
+            question_count: r.question_count,
+            duration_seconds: r.duration_seconds,
+            verified: r.verified,
+            created_at: r.created_at,
+        }
+    }
+}
+
+impl From<QuizAnswerRow> for QuizAnswer {
+    fn from(a: QuizAnswerRow) -> Self {
+        Self {
+            position: a.position,
+            taxon_id: a.taxon_id,
+            chosen_taxon_id: a.chosen_taxon_id,
+            correct: a.correct,
+            response_ms: a.response_ms,
+            observation_id: a.observation_id,
+            photo_id: a.photo_id,
+        }
+    }
+}
+
+impl From<QuizChoice> for QuizQuestionChoice {
+    fn from(c: QuizChoice) -> Self {
+        Self {
+            taxon_id: c.taxon_id,
+            name: c.name,
+            rank: c.rank,
+            common_name: c.common_name,
+        }
     }
 }
 
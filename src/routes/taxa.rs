@@ -0,0 +1,314 @@
+use poem_openapi::{
+    Object, OpenApi,
+    param::{Path, Query},
+    payload::Json,
+};
+
+use crate::clients::inat::{InatPhoto, InatTaxon};
+use crate::error::{ApiError, ApiResult};
+use crate::http_cache::cache_public;
+use crate::license::License;
+use crate::repos::taxon_repo::TaxonRepo;
+use crate::services::taxa::{self, DEFAULT_LOCALE, MirroredTaxon};
+use crate::state::AppState;
+
+pub struct TaxaApi {
+    pub state: AppState,
+}
+
+/// A taxon, independent of the iNaturalist API's response format
+#[derive(Object, Debug, Clone)]
+pub struct Taxon {
+    /// iNaturalist taxon id
+    pub id: i64,
+    /// Scientific name
+    pub name: String,
+    /// e.g. "species", "genus"
+    pub rank: Option<String>,
+    /// Numeric rank, lower is more specific (species = 10)
+    pub rank_level: Option<f64>,
+    /// Common name in the requested locale, if there is one
+    pub common_name: Option<String>,
+    /// Broad group used for icons, e.g. "Aves", "Plantae"
+    pub iconic_taxon: Option<String>,
+    /// Ids from the root of the tree of life down to the parent
+    pub ancestor_ids: Vec<i64>,
+    /// Ancestors in the same order as `ancestor_ids`. Only included by
+    /// `GET /taxa/{id}`.
+    pub ancestors: Vec<TaxonAncestor>,
+    pub default_photo: Option<TaxonPhoto>,
+    /// Common names in every locale. Only included by `GET /taxa/{id}`.
+    pub common_names: Vec<CommonName>,
+    /// Number of observations on iNaturalist
+    pub observations_count: Option<i64>,
+    pub wikipedia_url: Option<String>,
+    /// False for taxa that have been merged or replaced
+    pub is_active: bool,
+}
+
+#[derive(Object, Debug, Clone)]
+pub struct TaxonAncestor {
+    pub id: i64,
+    pub name: String,
+    pub rank: Option<String>,
+    pub common_name: Option<String>,
+}
+
+#[derive(Object, Debug, Clone)]
+pub struct TaxonPhoto {
+    pub id: i64,
+    /// 75px square thumbnail
+    pub square_url: Option<String>,
+    /// Up to 500px on the longest side
+    pub medium_url: Option<String>,
+    /// Credit line to display with the photo
+    pub attribution: String,
+    /// Normalised licence code, e.g. "cc-by-nc" or "all-rights-reserved"
+    pub license_code: String,
+    /// e.g. "CC BY-NC"
+    pub license_name: String,
+    /// Licence text; absent for all rights reserved
+    pub license_url: Option<String>,
+}
+
+#[derive(Object, Debug, Clone)]
+pub struct CommonName {
+    pub name: String,
+    /// e.g. "en", "es"
+    pub locale: String,
+}
+
+#[derive(Object)]
+struct TaxonSearchResponse {
+    items: Vec<Taxon>,
+}
+
+#[derive(Object)]
+struct TaxonListResponse {
+    items: Vec<Taxon>,
+}
+
+#[OpenApi(prefix_path = "/taxa")]
+impl TaxaApi {
+    /// Search taxa by scientific or common name, for autocomplete
+    #[oai(path = "/search", method = "get", transform = "cache_public")]
+    async fn search(
+        &self,
+        /// Part of a scientific or common name
+        q: Query<String>,
+        /// Locale for common names, e.g. "en"
+        locale: Query<Option<String>>,
+        #[oai(default = "default_limit")] limit: Query<u32>,
+    ) -> ApiResult<Json<TaxonSearchResponse>> {
+        let q = q.0.trim();
+        if q.is_empty() || q.len() > 100 {
+            return Err(ApiError::Validation(
+                "q must be between 1 and 100 characters".to_string(),
+            ));
+        }
+        let locale = validate_locale(locale.0)?;
+
+        let taxa = self
+            .state
+            .inat
+            .autocomplete_taxa(q, locale.as_deref(), limit.0.clamp(1, 30))
+            .await?;
+
+        Ok(Json(TaxonSearchResponse {
+            items: taxa.into_iter().map(Taxon::from).collect(),
+        }))
+    }
+
+    /// Get a taxon with its ancestors and common names
+    #[oai(path = "/:id", method = "get", transform = "cache_public")]
+    async fn get(
+        &self,
+        id: Path<i64>,
+        /// Locale for common names, e.g. "en"
+        locale: Query<Option<String>>,
+    ) -> ApiResult<Json<Taxon>> {
+        let locale = validate_locale(locale.0)?;
+
+        let taxon = taxa::get_taxon(&self.state, id.0, locale.as_deref())
+            .await?
+            .ok_or_else(|| ApiError::NotFound("taxon".to_string()))?;
+
+        Ok(Json(Taxon::from_mirror(taxon, locale.as_deref())))
+    }
+
+    /// List taxa below a taxon in the tree, most observed first. Only taxa
+    /// already in the local mirror are included.
+    #[oai(path = "/:id/descendants", method = "get", transform = "cache_public")]
+    async fn descendants(
+        &self,
+        id: Path<i64>,
+        /// Only include taxa of this rank, e.g. "species"
+        rank: Query<Option<String>>,
+        /// Locale for common names, e.g. "en"
+        locale: Query<Option<String>>,
+        #[oai(default = "default_page_size")] limit: Query<i64>,
+        #[oai(default)] offset: Query<i64>,
+    ) -> ApiResult<Json<TaxonListResponse>> {
+        let locale = validate_locale(locale.0)?;
+
+        // make sure the taxon itself is mirrored, so its path is known
+        taxa::get_taxon(&self.state, id.0, locale.as_deref())
+            .await?
+            .ok_or_else(|| ApiError::NotFound("taxon".to_string()))?;
+
+        let rows = TaxonRepo::new(self.state.db.clone())
+            .list_descendants(
+                id.0,
+                rank.0.as_deref(),
+                limit.0.clamp(1, 200),
+                offset.0.max(0),
+            )
+            .await?;
+
+        let items = rows
+            .into_iter()
+            .map(|taxon| {
+                Taxon::from_mirror(
+                    MirroredTaxon {
+                        taxon,
+                        ancestors: Vec::new(),
+                    },
+                    locale.as_deref(),
+                )
+            })
+            .collect();
+
+        Ok(Json(TaxonListResponse { items }))
+    }
+}
+
+fn default_limit() -> u32 {
+    10
+}
+
+fn default_page_size() -> i64 {
+    50
+}
+
+/// Accepts language tags like "en" or "pt-BR"
+pub(crate) fn validate_locale(locale: Option<String>) -> ApiResult<Option<String>> {
+    match locale {
+        Some(l)
+            if (2..=10).contains(&l.len())
+                && l.chars().all(|c| c.is_ascii_alphabetic() || c == '-') =>
+        {
+            Ok(Some(l))
+        }
+        Some(_) => Err(ApiError::Validation(
+            "locale must be a language tag such as \"en\"".to_string(),
+        )),
+        None => Ok(None),
+    }
+}
+
+impl From<InatTaxon> for Taxon {
+    fn from(t: InatTaxon) -> Self {
+        let ancestor_ids = t.ancestor_ids();
+
+        let ancestors = t
+            .ancestors
+            .unwrap_or_default()
+            .into_iter()
+            .map(|a| TaxonAncestor {
+                id: a.id,
+                name: a.name,
+                rank: a.rank,
+                common_name: a.preferred_common_name,
+            })
+            .collect();
+
+        let common_names = t
+            .names
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|n| n.lexicon.as_deref() != Some("scientific-names"))
+            .filter(|n| n.is_valid.unwrap_or(true))
+            .filter_map(|n| {
+                Some(CommonName {
+                    name: n.name,
+                    locale: n.locale?,
+                })
+            })
+            .collect();
+
+        Self {
+            id: t.id,
+            name: t.name,
+            rank: t.rank,
+            rank_level: t.rank_level,
+            common_name: t.preferred_common_name,
+            iconic_taxon: t.iconic_taxon_name,
+            ancestor_ids,
+            ancestors,
+            default_photo: t.default_photo.map(TaxonPhoto::from),
+            common_names,
+            observations_count: t.observations_count,
+            wikipedia_url: t.wikipedia_url,
+            is_active: t.is_active.unwrap_or(true),
+        }
+    }
+}
+
+impl Taxon {
+    fn from_mirror(mirrored: MirroredTaxon, locale: Option<&str>) -> Self {
+        let locale = locale.unwrap_or(DEFAULT_LOCALE).to_lowercase();
+        let MirroredTaxon { taxon, ancestors } = mirrored;
+        let ancestor_ids = taxon.ancestor_ids();
+
+        let ancestors = ancestors
+            .into_iter()
+            .map(|a| TaxonAncestor {
+                common_name: a.common_names.get(&locale).cloned(),
+                id: a.id,
+                name: a.name,
+                rank: a.rank,
+            })
+            .collect();
+
+        let mut common_names: Vec<_> = taxon
+            .common_names
+            .iter()
+            .map(|(locale, name)| CommonName {
+                name: name.clone(),
+                locale: locale.clone(),
+            })
+            .collect();
+        common_names.sort_by(|a, b| a.locale.cmp(&b.locale));
+
+        Self {
+            common_name: taxon.common_names.get(&locale).cloned(),
+            id: taxon.id,
+            name: taxon.name,
+            rank: taxon.rank,
+            rank_level: taxon.rank_level,
+            iconic_taxon: taxon.iconic_taxon_name,
+            ancestor_ids,
+            ancestors,
+            default_photo: taxon.default_photo.map(|p| TaxonPhoto::from(p.0)),
+            common_names,
+            observations_count: taxon.observations_count,
+            wikipedia_url: taxon.wikipedia_url,
+            is_active: taxon.is_active,
+        }
+    }
+}
+
+impl From<InatPhoto> for TaxonPhoto {
+    fn from(p: InatPhoto) -> Self {
+        let license = License::from_inat(p.license_code.as_deref());
+        Self {
+            id: p.id,
+            square_url: p.square_url.or(p.url),
+            medium_url: p.medium_url,
+            attribution: license.attribution(p.attribution.as_deref(), None),
+            license_code: license.code().to_string(),
+            license_name: license.name().to_string(),
+            license_url: license.url().map(str::to_string),
+        }
+    }
+}
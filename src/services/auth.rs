@@ -1,12 +1,9 @@
-use poem::Error as PoemError;
-use poem::Result as PoemResult;
-use poem::http::StatusCode;
 use poem::web::cookie::CookieJar;
 use sqlx::FromRow;
 
-use crate::internal_error;
+use crate::error::{ApiError, ApiResult};
 use crate::session_store::SessionStore;
-use crate::state::AppState; // the helper we defined earlier
+use crate::state::AppState;
 
 #[derive(FromRow)]
 pub struct UserRow {
@@ -16,40 +13,46 @@ pub struct UserRow {
 }
 
 // Helper: get current user row or return 401/500
-pub async fn get_current_user(state: &AppState, jar: &CookieJar) -> PoemResult<UserRow> {
+pub async fn get_current_user(state: &AppState, jar: &CookieJar) -> ApiResult<UserRow> {
     // 1) Read session cookie
     let cookie = jar
         .get("taxonia_session")
-        .ok_or_else(|| PoemError::from_status(StatusCode::UNAUTHORIZED))?;
+        .ok_or(ApiError::Unauthenticated)?;
 
-    let session_id: String = cookie
-        .value()
-        .map_err(|_| PoemError::from_status(StatusCode::UNAUTHORIZED))?;
+    let session_id: String = cookie.value().map_err(|_| ApiError::Unauthenticated)?;
 
     // 2) Resolve session via Redis
-    let session_store = SessionStore::new(state.redis.clone());
+    let session_store = SessionStore::new(state.redis.clone(), &state.config);
     let session = session_store
         .get_session(&session_id)
-        .await
-        .map_err(|e| internal_error("get_session failed", e))?;
-
-    let session = match session {
-        Some(s) => s,
-        None => return Err(PoemError::from_status(StatusCode::UNAUTHORIZED)),
-    };
+        .await?
+        .ok_or(ApiError::SessionExpired)?;
 
     // 3) Fetch user row from DB
     let user: UserRow = sqlx::query_as(
         r#"
         SELECT id, display_name, primary_email
         FROM users
-        WHERE id = $1
+        WHERE id = $1 AND deleted_at IS NULL
         "#,
     )
     .bind(session.user_id)
-    .fetch_one(&state.db)
-    .await
-    .map_err(|e| internal_error("fetch_current_user_row failed", e))?;
+    .fetch_optional(&state.db)
+    .await?
+    // the user was deleted while the session was still alive
+    .ok_or(ApiError::SessionExpired)?;
+
+    tracing::Span::current().record("user_id", user.id);
 
     Ok(user)
 }
+
+/// Like [`get_current_user`], but `None` for anonymous requests or ones whose
+/// session has expired
+pub async fn get_optional_user(state: &AppState, jar: &CookieJar) -> ApiResult<Option<UserRow>> {
+    match get_current_user(state, jar).await {
+        Ok(user) => Ok(Some(user)),
+        Err(ApiError::Unauthenticated | ApiError::SessionExpired) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
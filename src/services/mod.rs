@@ -1,2 +1,6 @@
 pub mod auth;
+pub mod observations;
+pub mod quiz;
 pub mod rand;
+pub mod taxa;
+pub mod taxonomy_import;
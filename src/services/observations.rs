@@ -0,0 +1,107 @@
+use std::collections::HashSet;
+
+use tracing::warn;
+
+use crate::clients::inat::{InatObservation, MAX_PER_PAGE, ObservationFilter};
+use crate::error::ApiResult;
+use crate::license::License;
+use crate::repos::observation_repo::ObservationRepo;
+use crate::state::AppState;
+
+/// Random pages requested before settling for fewer observations than asked
+const MAX_SAMPLE_PAGES: usize = 3;
+
+/// Up to `count` random research-grade observations with photos matching
+/// `filter`. Photos under licences the filter doesn't allow are removed. For
+/// a signed-in user, observations they've already been shown are skipped
+/// unless `include_seen` is set, and the returned ones are remembered as
+/// seen.
+pub async fn sample(
+    state: &AppState,
+    user_id: Option<i64>,
+    filter: &ObservationFilter,
+    locale: Option<&str>,
+    count: usize,
+    include_seen: bool,
+) -> ApiResult<Vec<InatObservation>> {
+    let exclude_seen_by = user_id.filter(|_| !include_seen);
+    let picked = pick(state, exclude_seen_by, filter, locale, count).await?;
+    if let Some(user_id) = user_id {
+        mark_seen(state, user_id, &picked).await;
+    }
+    Ok(picked)
+}
+
+/// Like [`sample`], but doesn't record anything as seen. Observations
+/// already shown to `exclude_seen_by` are skipped.
+pub async fn pick(
+    state: &AppState,
+    exclude_seen_by: Option<i64>,
+    filter: &ObservationFilter,
+    locale: Option<&str>,
+    count: usize,
+) -> ApiResult<Vec<InatObservation>> {
+    let repo = ObservationRepo::new(state.db.clone());
+
+    let mut picked: Vec<InatObservation> = Vec::with_capacity(count);
+    let mut ids = HashSet::new();
+    for _ in 0..MAX_SAMPLE_PAGES {
+        let page = state
+            .inat
+            .sample_observations(filter, locale, MAX_PER_PAGE)
+            .await?;
+        let exhausted = page.len() < MAX_PER_PAGE;
+
+        let seen: HashSet<i64> = match exclude_seen_by {
+            Some(user_id) => {
+                let page_ids: Vec<_> = page.iter().map(|o| o.id).collect();
+                repo.list_seen(user_id, &page_ids)
+                    .await?
+                    .into_iter()
+                    .collect()
+            }
+            None => HashSet::new(),
+        };
+
+        let usable = page
+            .into_iter()
+            .filter(|o| o.taxon.is_some() && !seen.contains(&o.id))
+            .map(|mut o| {
+                if !filter.photo_licenses.is_empty() {
+                    o.photos.retain(|p| {
+                        let license = License::from_inat(p.license_code.as_deref());
+                        filter.photo_licenses.contains(&license)
+                    });
+                }
+                o
+            })
+            .filter(|o| !o.photos.is_empty());
+        for observation in usable {
+            if picked.len() < count && ids.insert(observation.id) {
+                picked.push(observation);
+            }
+        }
+
+        // a short page means every match was already returned
+        if picked.len() == count || exhausted {
+            break;
+        }
+    }
+
+    Ok(picked)
+}
+
+/// Remember that the user has been shown `observations`
+pub async fn mark_seen(state: &AppState, user_id: i64, observations: &[InatObservation]) {
+    if observations.is_empty() {
+        return;
+    }
+    let ids: Vec<_> = observations.iter().map(|o| o.id).collect();
+    // not worth failing the request over
+    if let Err(e) = ObservationRepo::new(state.db.clone())
+        .mark_seen(user_id, &ids)
+        .await
+    {
+        warn!(user_id, "failed to record seen observations: {e:#}");
+    }
+}
@@ -0,0 +1,926 @@
+use std::collections::{HashMap, HashSet};
+
+use poem_openapi::Enum;
+use rand::seq::{IndexedRandom, SliceRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::clients::inat::{
+    InatObservation, InatSpeciesCount, MAX_PER_PAGE, ObservationFilter, SpeciesCountFilter,
+};
+use crate::error::{ApiError, ApiResult};
+use crate::license::REUSABLE;
+use crate::repos::quiz_repo::{
+    CompletedQuiz, QuizChoice, QuizQuestionRow, QuizRepo, QuizSessionRow,
+};
+use crate::repos::taxon_repo::TaxonRepo;
+use crate::services::observations;
+use crate::services::rand::generate_random_id;
+use crate::services::taxa::{self, DEFAULT_LOCALE};
+use crate::state::AppState;
+
+/// `quiz_type` of results saved from quiz sessions
+pub const QUIZ_TYPE: &str = "inat_observations";
+
+/// Answers offered per question, including the correct one
+pub const CHOICES: usize = 4;
+
+/// Ranks a quiz can ask for
+pub const RANKS: &[&str] = &["species", "genus", "family", "order", "class"];
+
+/// Most observed species considered when building the answer pool
+const POOL_SIZE: u32 = 200;
+
+/// Observations fetched to pick questions from
+const OBSERVATION_SAMPLE: usize = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, Enum)]
+#[oai(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum Difficulty {
+    /// Commonly observed taxa, with distractors from distant groups
+    Easy,
+    /// Any taxa in the pool, with random distractors
+    #[default]
+    Medium,
+    /// Any taxa in the pool, with the closest relatives as distractors
+    Hard,
+}
+
+/// What a quiz is generated from; stored with the session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuizParams {
+    /// Only taxa below this one
+    pub taxon_id: Option<i64>,
+    /// Only taxa observed in this iNaturalist place
+    pub place_id: Option<i64>,
+    /// Rank the answers are at, one of [`RANKS`]
+    pub rank: String,
+    pub question_count: usize,
+    pub difficulty: Difficulty,
+    /// Locale of common names in the choices
+    pub locale: Option<String>,
+}
+
+/// A stored quiz and its questions
+pub struct QuizSession {
+    pub session: QuizSessionRow,
+    pub questions: Vec<QuizQuestionRow>,
+}
+
+/// The outcome of answering a question
+pub struct Answer {
+    pub question: QuizQuestionRow,
+    /// Set when this was the last question
+    pub completed: Option<CompletedQuiz>,
+}
+
+/// A taxon that can be the answer to a question
+struct Candidate {
+    choice: QuizChoice,
+    /// Ids from the root down to, and including, the taxon
+    lineage: Vec<i64>,
+    /// Research-grade observations matching the quiz filters
+    count: i64,
+}
+
+/// Generate a quiz from iNaturalist data and store it. Questions use
+/// observations with reusable photo licences that a signed-in user hasn't
+/// seen before; those used are then marked as seen.
+pub async fn create_session(
+    state: &AppState,
+    user_id: Option<i64>,
+    params: QuizParams,
+) -> ApiResult<QuizSession> {
+    let pool = answer_pool(state, &params).await?;
+    if pool.len() < CHOICES {
+        return Err(ApiError::Validation(
+            "too few taxa match these filters to build a quiz".to_string(),
+        ));
+    }
+
+    // only observations that roll up into the pool can become questions
+    let mut taxon_ids: Vec<i64> = pool.keys().copied().collect();
+    taxon_ids.sort_unstable();
+    let filter = ObservationFilter {
+        taxon_ids,
+        place_id: params.place_id,
+        photo_licenses: REUSABLE.to_vec(),
+        ..Default::default()
+    };
+    let observations = observations::pick(
+        state,
+        user_id,
+        &filter,
+        params.locale.as_deref(),
+        OBSERVATION_SAMPLE,
+    )
+    .await?;
+
+    let (questions, used) = build_questions(&pool, observations, &params);
+    if questions.is_empty() {
+        return Err(ApiError::Validation(
+            "no observations with reusable photos match these filters".to_string(),
+        ));
+    }
+
+    let params_json = serde_json::to_value(&params).map_err(anyhow::Error::from)?;
+    let session = QuizRepo::new(state.db.clone())
+        .insert_session(&generate_random_id(), user_id, &params_json, &questions)
+        .await?;
+
+    if let Some(user_id) = user_id {
+        observations::mark_seen(state, user_id, &used).await;
+    }
+
+    Ok(QuizSession { session, questions })
+}
+
+/// A stored quiz, or `None` if there's no session with that id
+pub async fn get_session(state: &AppState, id: &str) -> ApiResult<Option<QuizSession>> {
+    let repo = QuizRepo::new(state.db.clone());
+    let Some(session) = repo.get_session(id).await? else {
+        return Ok(None);
+    };
+    let questions = repo.list_questions(id).await?;
+    Ok(Some(QuizSession { session, questions }))
+}
+
+/// Check the answer to a question and record it. Questions are answered in
+/// order, once each. After the last one the server computes the score and,
+/// for a signed-in owner, saves it as a verified result. Quizzes created by
+/// a signed-in user can only be answered by that user.
+pub async fn answer(
+    state: &AppState,
+    session_id: &str,
+    user_id: Option<i64>,
+    position: i32,
+    taxon_id: i64,
+) -> ApiResult<Answer> {
+    let repo = QuizRepo::new(state.db.clone());
+    let session = repo
+        .get_session(session_id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("quiz session".to_string()))?;
+    match (session.user_id, user_id) {
+        (Some(_), None) => return Err(ApiError::Unauthenticated),
+        (Some(owner), Some(user)) if owner != user => {
+            return Err(ApiError::Forbidden(
+                "this quiz belongs to another user".to_string(),
+            ));
+        }
+        _ => {}
+    }
+    if session.completed_at.is_some() {
+        return Err(ApiError::Conflict("quiz is already completed".to_string()));
+    }
+
+    let Some(question) = repo.answer_question(session_id, position, taxon_id).await? else {
+        // work out why it couldn't be answered
+        let questions = repo.list_questions(session_id).await?;
+        let Some(question) = questions.iter().find(|q| q.position == position) else {
+            return Err(ApiError::NotFound("question".to_string()));
+        };
+        if question.answered_at.is_some() {
+            return Err(ApiError::Conflict(
+                "question was already answered".to_string(),
+            ));
+        }
+        if !question.choices.iter().any(|c| c.taxon_id == taxon_id) {
+            return Err(ApiError::Validation(
+                "taxon_id is not one of the question's choices".to_string(),
+            ));
+        }
+        let next = questions
+            .iter()
+            .find(|q| q.answered_at.is_none())
+            .map_or(position, |q| q.position);
+        return Err(ApiError::Conflict(format!(
+            "questions must be answered in order; the next one is {next}"
+        )));
+    };
+
+    let completed = repo.complete_session(session_id, QUIZ_TYPE).await?;
+    Ok(Answer {
+        question,
+        completed,
+    })
+}
+
+/// Taxa at the quiz's rank observed within its filters, keyed by id. Built
+/// from iNaturalist's species checklist, rolling species up to their
+/// ancestor at the rank through the local taxonomy mirror.
+async fn answer_pool(state: &AppState, params: &QuizParams) -> ApiResult<HashMap<i64, Candidate>> {
+    let filter = SpeciesCountFilter {
+        taxon_id: params.taxon_id,
+        ..Default::default()
+    };
+    let locale = params.locale.as_deref();
+    let counts = state
+        .inat
+        .species_counts(params.place_id, &filter, locale, POOL_SIZE, 1)
+        .await?;
+
+    let mut pool = HashMap::new();
+    if params.rank == "species" {
+        for c in counts.results {
+            let mut lineage = c.taxon.ancestor_ids();
+            lineage.push(c.taxon.id);
+            let choice = QuizChoice {
+                taxon_id: c.taxon.id,
+                name: c.taxon.name,
+                rank: c.taxon.rank,
+                common_name: c.taxon.preferred_common_name,
+            };
+            pool.insert(
+                choice.taxon_id,
+                Candidate {
+                    choice,
+                    lineage,
+                    count: c.count,
+                },
+            );
+        }
+        return Ok(pool);
+    }
+
+    // the checklist doesn't include ancestors' ranks, so look them up
+    let repo = TaxonRepo::new(state.db.clone());
+    let ancestor_ids: Vec<i64> = counts
+        .results
+        .iter()
+        .flat_map(|c| c.taxon.ancestor_ids())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let mut ancestors = repo.get_many(&ancestor_ids).await?;
+    let known: HashSet<i64> = ancestors.iter().map(|a| a.id).collect();
+    let lookups = lineage_lookups(&counts.results, &known);
+    if !lookups.is_empty() {
+        taxa::refresh_taxa(&repo, &state.inat, &lookups, locale).await?;
+        ancestors = repo.get_many(&ancestor_ids).await?;
+    }
+    let ancestors: HashMap<i64, _> = ancestors.into_iter().map(|a| (a.id, a)).collect();
+
+    let locale = locale.unwrap_or(DEFAULT_LOCALE).to_lowercase();
+    for c in counts.results {
+        let Some(ancestor) = c
+            .taxon
+            .ancestor_ids()
+            .iter()
+            .filter_map(|id| ancestors.get(id))
+            .find(|a| a.rank.as_deref() == Some(params.rank.as_str()))
+        else {
+            continue;
+        };
+        pool.entry(ancestor.id)
+            .or_insert_with(|| {
+                let mut lineage = ancestor.ancestor_ids();
+                lineage.push(ancestor.id);
+                Candidate {
+                    choice: QuizChoice {
+                        taxon_id: ancestor.id,
+                        name: ancestor.name.clone(),
+                        rank: ancestor.rank.clone(),
+                        common_name: ancestor.common_names.get(&locale).cloned(),
+                    },
+                    lineage,
+                    count: 0,
+                }
+            })
+            .count += c.count;
+    }
+    Ok(pool)
+}
+
+/// Species to fetch so that the mirror learns the missing ancestors, at most
+/// one page so it takes a single request. Fetching a taxon stores its whole
+/// lineage, so one species covers every missing ancestor it shares with
+/// others. Lineages left out are looked up by later quizzes.
+fn lineage_lookups(counts: &[InatSpeciesCount], known: &HashSet<i64>) -> Vec<i64> {
+    let mut covered = known.clone();
+    let mut lookups = Vec::new();
+    for c in counts {
+        if lookups.len() == MAX_PER_PAGE {
+            break;
+        }
+        let lineage = c.taxon.ancestor_ids();
+        if lineage.iter().any(|id| !covered.contains(id)) {
+            covered.extend(lineage);
+            lookups.push(c.taxon.id);
+        }
+    }
+    lookups
+}
+
+/// Questions for as many of `observations` as have an answer in the pool, up
+/// to the requested count, preferring a different answer for each. Returns
+/// the questions and the observations used.
+fn build_questions(
+    pool: &HashMap<i64, Candidate>,
+    observations: Vec<InatObservation>,
+    params: &QuizParams,
+) -> (Vec<QuizQuestionRow>, Vec<InatObservation>) {
+    let mut rng = rand::rng();
+
+    let mut by_count: Vec<&Candidate> = pool.values().collect();
+    by_count.sort_by_key(|c| std::cmp::Reverse(c.count));
+    let common: HashSet<i64> = by_count[..by_count.len().div_ceil(2)]
+        .iter()
+        .map(|c| c.choice.taxon_id)
+        .collect();
+
+    // pair each observation with the pool taxon it belongs to
+    let mut matched: Vec<(InatObservation, &Candidate)> = observations
+        .into_iter()
+        .filter_map(|o| {
+            let taxon = o.taxon.as_ref()?;
+            let answer = taxon
+                .ancestor_ids()
+                .into_iter()
+                .chain([taxon.id])
+                .find_map(|id| pool.get(&id))?;
+            Some((o, answer))
+        })
+        .collect();
+    matched.shuffle(&mut rng);
+
+    if params.difficulty == Difficulty::Easy {
+        // stick to common taxa, unless there aren't enough of them
+        let easy = matched
+            .iter()
+            .filter(|(_, a)| common.contains(&a.choice.taxon_id))
+            .count();
+        if easy >= params.question_count {
+            matched.retain(|(_, a)| common.contains(&a.choice.taxon_id));
+        }
+    }
+
+    // distinct answers first, then repeats
+    let mut answers = HashSet::new();
+    let (mut picked, repeats): (Vec<_>, Vec<_>) = matched
+        .into_iter()
+        .partition(|(_, a)| answers.insert(a.choice.taxon_id));
+    picked.extend(repeats);
+    picked.truncate(params.question_count);
+
+    let mut questions = Vec::with_capacity(picked.len());
+    let mut used = Vec::with_capacity(picked.len());
+    for (position, (observation, answer)) in picked.into_iter().enumerate() {
+        let mut choices: Vec<QuizChoice> = distractors(pool, answer, params.difficulty)
+            .into_iter()
+            .map(|c| c.choice.clone())
+            .chain([answer.choice.clone()])
+            .collect();
+        choices.shuffle(&mut rng);
+
+        questions.push(QuizQuestionRow {
+            position: position as i32,
+            observation_id: observation.id,
+            observer: observation
+                .user
+                .as_ref()
+                .map(|u| u.name.clone().unwrap_or_else(|| u.login.clone())),
+            photos: sqlx::types::Json(observation.photos.clone()),
+            correct_taxon_id: answer.choice.taxon_id,
+            choices: sqlx::types::Json(choices),
+            chosen_taxon_id: None,
+            is_correct: None,
+            answered_at: None,
+            response_ms: None,
+        });
+        used.push(observation);
+    }
+    (questions, used)
+}
+
+/// Wrong answers for a question: the least related taxa in the pool for easy
+/// quizzes, the closest relatives for hard ones, and any for medium. Fewer
+/// than `CHOICES - 1` if the pool is too small.
+fn distractors<'a>(
+    pool: &'a HashMap<i64, Candidate>,
+    answer: &Candidate,
+    difficulty: Difficulty,
+) -> Vec<&'a Candidate> {
+    let mut rng = rand::rng();
+    let mut others: Vec<&Candidate> = pool
+        .values()
+        .filter(|c| c.choice.taxon_id != answer.choice.taxon_id)
+        .collect();
+    // shuffle first so ties are broken randomly
+    others.shuffle(&mut rng);
+    others.sort_by_key(|c| shared_ancestors(&c.lineage, &answer.lineage));
+
+    let wanted = CHOICES - 1;
+    match difficulty {
+        Difficulty::Easy => {
+            let distant = &others[..others.len().div_ceil(2).max(wanted).min(others.len())];
+            distant.choose_multiple(&mut rng, wanted).copied().collect()
+        }
+        Difficulty::Medium => others.choose_multiple(&mut rng, wanted).copied().collect(),
+        Difficulty::Hard => others.into_iter().rev().take(wanted).collect(),
+    }
+}
+
+/// Length of the common prefix of two lineages
+fn shared_ancestors(a: &[i64], b: &[i64]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::clients::inat::InatClient;
+
+    /// Two families of two genera with two species each:
+    /// 1 > 10 > 100 > {1000, 1001}, 1 > 10 > 101 > {1010, 1011},
+    /// 1 > 11 > 110 > {1100, 1101}, 1 > 11 > 111 > {1110, 1111}
+    fn pool() -> HashMap<i64, Candidate> {
+        let mut pool = HashMap::new();
+        for (i, family) in [10, 11].into_iter().enumerate() {
+            for genus in [family * 10, family * 10 + 1] {
+                for species in [genus * 10, genus * 10 + 1] {
+                    pool.insert(
+                        species,
+                        candidate(species, vec![1, family, genus, species], (i + 1) as i64),
+                    );
+                }
+            }
+        }
+        pool
+    }
+
+    fn candidate(id: i64, lineage: Vec<i64>, count: i64) -> Candidate {
+        Candidate {
+            choice: QuizChoice {
+                taxon_id: id,
+                name: format!("taxon {id}"),
+                rank: Some("species".to_string()),
+                common_name: None,
+            },
+            lineage,
+            count,
+        }
+    }
+
+    fn observation(id: i64, taxon: &Candidate) -> InatObservation {
+        let (&taxon_id, ancestors) = taxon.lineage.split_last().unwrap();
+        serde_json::from_value(json!({
+            "id": id,
+            "taxon": {
+                "id": taxon_id,
+                "name": format!("taxon {taxon_id}"),
+                "ancestor_ids": ancestors.iter().chain([&taxon_id]).collect::<Vec<_>>(),
+            },
+            "photos": [{ "id": id * 10, "url": "https://example.org/square.jpg" }],
+            "user": { "id": 1, "login": "observer" },
+        }))
+        .unwrap()
+    }
+
+    fn params(question_count: usize, difficulty: Difficulty) -> QuizParams {
+        QuizParams {
+            taxon_id: None,
+            place_id: None,
+            rank: "species".to_string(),
+            question_count,
+            difficulty,
+            locale: None,
+        }
+    }
+
+    /// One observation of every taxon in the pool
+    fn observations(pool: &HashMap<i64, Candidate>) -> Vec<InatObservation> {
+        pool.values()
+            .enumerate()
+            .map(|(i, c)| observation(i as i64 + 1, c))
+            .collect()
+    }
+
+    fn assert_valid(question: &QuizQuestionRow, choices: usize) {
+        let ids: Vec<i64> = question.choices.iter().map(|c| c.taxon_id).collect();
+        assert_eq!(ids.len(), choices, "{ids:?}");
+        assert_eq!(
+            ids.iter()
+                .filter(|&&id| id == question.correct_taxon_id)
+                .count(),
+            1,
+            "{ids:?}"
+        );
+        assert_eq!(
+            ids.iter().collect::<HashSet<_>>().len(),
+            ids.len(),
+            "{ids:?}"
+        );
+    }
+
+    #[test]
+    fn questions_have_one_correct_choice_and_no_duplicates() {
+        let pool = pool();
+        for difficulty in [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard] {
+            let (questions, used) =
+                build_questions(&pool, observations(&pool), &params(6, difficulty));
+
+            assert_eq!(questions.len(), 6);
+            assert_eq!(used.len(), 6);
+            for (position, question) in questions.iter().enumerate() {
+                assert_eq!(question.position, position as i32);
+                assert_valid(question, CHOICES);
+            }
+            // a different answer for each question while there are enough
+            let answers: HashSet<_> = questions.iter().map(|q| q.correct_taxon_id).collect();
+            assert_eq!(answers.len(), 6);
+        }
+    }
+
+    #[test]
+    fn observations_outside_the_pool_are_skipped() {
+        let pool = pool();
+        let stray = candidate(2000, vec![1, 20, 200, 2000], 1);
+        let observations = vec![observation(1, &stray), observation(2, &pool[&1000])];
+
+        let (questions, used) =
+            build_questions(&pool, observations, &params(5, Difficulty::Medium));
+
+        assert_eq!(questions.len(), 1);
+        assert_eq!(questions[0].correct_taxon_id, 1000);
+        assert_eq!(used[0].id, 2);
+    }
+
+    #[test]
+    fn hard_distractors_are_the_closest_relatives() {
+        let pool = pool();
+        let distractors = distractors(&pool, &pool[&1000], Difficulty::Hard);
+
+        let ids: HashSet<i64> = distractors.iter().map(|c| c.choice.taxon_id).collect();
+        // the sister species, then the species of the sister genus
+        assert_eq!(ids, HashSet::from([1001, 1010, 1011]));
+    }
+
+    #[test]
+    fn easy_distractors_come_from_distant_taxa() {
+        let pool = pool();
+        for _ in 0..20 {
+            for c in distractors(&pool, &pool[&1000], Difficulty::Easy) {
+                assert_eq!(
+                    c.lineage[1], 11,
+                    "{} is in the same family",
+                    c.choice.taxon_id
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn easy_quizzes_fall_back_to_rarer_taxa() {
+        let pool = pool();
+        // only taxa in the less observed family
+        let observations: Vec<_> = pool
+            .values()
+            .filter(|c| c.lineage[1] == 10)
+            .enumerate()
+            .map(|(i, c)| observation(i as i64 + 1, c))
+            .collect();
+
+        let (questions, _) = build_questions(&pool, observations, &params(4, Difficulty::Easy));
+
+        assert_eq!(questions.len(), 4);
+    }
+
+    #[test]
+    fn small_pools_offer_every_other_taxon() {
+        let pool: HashMap<i64, Candidate> = [
+            (1000, candidate(1000, vec![1, 10, 100, 1000], 1)),
+            (1100, candidate(1100, vec![1, 11, 110, 1100], 1)),
+        ]
+        .into_iter()
+        .collect();
+
+        for difficulty in [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard] {
+            let (questions, _) =
+                build_questions(&pool, observations(&pool), &params(3, difficulty));
+
+            assert_eq!(questions.len(), 2);
+            for question in &questions {
+                assert_valid(question, 2);
+            }
+        }
+    }
+
+    #[test]
+    fn one_species_is_looked_up_per_unknown_lineage() {
+        let counts: Vec<InatSpeciesCount> = (0..100)
+            .map(|i| {
+                // ten species in each of ten genera
+                let genus = 500 + i / 10;
+                serde_json::from_value(json!({
+                    "count": 1,
+                    "taxon": { "id": 1000 + i, "name": "species", "ancestry": format!("1/2/{genus}") },
+                }))
+                .unwrap()
+            })
+            .collect();
+        let known = HashSet::from([1, 2, 500, 501]);
+
+        let lookups = lineage_lookups(&counts, &known);
+
+        assert_eq!(
+            lookups,
+            vec![1020, 1030, 1040, 1050, 1060, 1070, 1080, 1090]
+        );
+
+        let many: Vec<InatSpeciesCount> = (0..100)
+            .map(|i| {
+                serde_json::from_value(json!({
+                    "count": 1,
+                    "taxon": { "id": 1000 + i, "name": "species", "ancestry": format!("1/{}", 500 + i) },
+                }))
+                .unwrap()
+            })
+            .collect();
+        assert_eq!(lineage_lookups(&many, &known).len(), MAX_PER_PAGE);
+    }
+
+    #[test]
+    fn answers_repeat_only_once_every_taxon_is_used() {
+        let pool: HashMap<i64, Candidate> = [1000, 1001, 1010, 1011]
+            .into_iter()
+            .map(|id| (id, candidate(id, vec![1, 10, id / 10, id], 1)))
+            .collect();
+        let observations: Vec<_> = (0..3)
+            .flat_map(|round| {
+                pool.values()
+                    .map(move |c| observation(round * 100 + c.choice.taxon_id, c))
+            })
+            .collect();
+
+        let (questions, _) = build_questions(&pool, observations, &params(4, Difficulty::Medium));
+
+        let answers: HashSet<_> = questions.iter().map(|q| q.correct_taxon_id).collect();
+        assert_eq!(answers.len(), 4);
+    }
+
+    async fn insert_user(pool: &PgPool) -> i64 {
+        sqlx::query_scalar("INSERT INTO users (display_name) VALUES ('tester') RETURNING id")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    /// A stored quiz whose answers are 1000, 1010 and 1100 in that order, each
+    /// offered alongside 1111
+    async fn insert_session(state: &AppState, user_id: Option<i64>) -> String {
+        let pool = pool();
+        let questions: Vec<QuizQuestionRow> = [1000, 1010, 1100]
+            .iter()
+            .enumerate()
+            .map(|(position, id)| QuizQuestionRow {
+                position: position as i32,
+                observation_id: position as i64 + 1,
+                observer: None,
+                photos: sqlx::types::Json(Vec::new()),
+                correct_taxon_id: *id,
+                choices: sqlx::types::Json(vec![
+                    pool[id].choice.clone(),
+                    pool[&1111].choice.clone(),
+                ]),
+                chosen_taxon_id: None,
+                is_correct: None,
+                answered_at: None,
+                response_ms: None,
+            })
+            .collect();
+        let id = generate_random_id();
+        QuizRepo::new(state.db.clone())
+            .insert_session(&id, user_id, &json!({}), &questions)
+            .await
+            .unwrap();
+        id
+    }
+
+    fn error(result: ApiResult<Answer>) -> ApiError {
+        match result {
+            Ok(answer) => panic!("answered question {}", answer.question.position),
+            Err(e) => e,
+        }
+    }
+
+    #[sqlx::test]
+    async fn questions_are_answered_in_order_once(db: PgPool) {
+        let state = AppState::for_tests(db);
+        let id = insert_session(&state, None).await;
+
+        let skipped = error(answer(&state, &id, None, 1, 1010).await);
+        assert!(matches!(skipped, ApiError::Conflict(_)), "{skipped:?}");
+
+        let first = answer(&state, &id, None, 0, 1000).await.unwrap();
+        assert_eq!(first.question.is_correct, Some(true));
+        assert!(first.question.response_ms.is_some());
+        assert!(first.completed.is_none());
+
+        let again = error(answer(&state, &id, None, 0, 1111).await);
+        assert!(matches!(again, ApiError::Conflict(_)), "{again:?}");
+        let missing = error(answer(&state, &id, None, 7, 1000).await);
+        assert!(matches!(missing, ApiError::NotFound(_)), "{missing:?}");
+        let unknown = error(answer(&state, "no-such-quiz", None, 0, 1000).await);
+        assert!(matches!(unknown, ApiError::NotFound(_)), "{unknown:?}");
+    }
+
+    #[sqlx::test]
+    async fn only_offered_choices_are_accepted(db: PgPool) {
+        let state = AppState::for_tests(db);
+        let id = insert_session(&state, None).await;
+
+        let rejected = error(answer(&state, &id, None, 0, 1001).await);
+        assert!(matches!(rejected, ApiError::Validation(_)), "{rejected:?}");
+
+        // the question can still be answered
+        let wrong = answer(&state, &id, None, 0, 1111).await.unwrap();
+        assert_eq!(wrong.question.chosen_taxon_id, Some(1111));
+        assert_eq!(wrong.question.is_correct, Some(false));
+    }
+
+    #[sqlx::test]
+    async fn only_the_owner_answers_their_quiz(db: PgPool) {
+        let owner = insert_user(&db).await;
+        let other = insert_user(&db).await;
+        let state = AppState::for_tests(db);
+        let id = insert_session(&state, Some(owner)).await;
+
+        let anonymous = error(answer(&state, &id, None, 0, 1000).await);
+        assert!(
+            matches!(anonymous, ApiError::Unauthenticated),
+            "{anonymous:?}"
+        );
+        let stranger = error(answer(&state, &id, Some(other), 0, 1000).await);
+        assert!(matches!(stranger, ApiError::Forbidden(_)), "{stranger:?}");
+
+        answer(&state, &id, Some(owner), 0, 1000).await.unwrap();
+    }
+
+    #[sqlx::test]
+    async fn completing_saves_one_verified_result(db: PgPool) {
+        let user_id = insert_user(&db).await;
+        let state = AppState::for_tests(db.clone());
+        let id = insert_session(&state, Some(user_id)).await;
+
+        answer(&state, &id, Some(user_id), 0, 1000).await.unwrap();
+        answer(&state, &id, Some(user_id), 1, 1111).await.unwrap();
+        let last = answer(&state, &id, Some(user_id), 2, 1100).await.unwrap();
+
+        let completed = last.completed.expect("quiz completed");
+        assert!((completed.score - 2.0 / 3.0).abs() < 1e-9);
+        let result_id = completed.result_id.expect("result saved");
+
+        let results: Vec<(i64, bool, f64, Option<i32>)> = sqlx::query_as(
+            "SELECT id, verified, score, question_count FROM quiz_results WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, result_id);
+        assert!(results[0].1);
+        assert_eq!(results[0].2, completed.score);
+        assert_eq!(results[0].3, Some(3));
+
+        let repo = QuizRepo::new(db.clone());
+        assert!(
+            repo.complete_session(&id, QUIZ_TYPE)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let after = error(answer(&state, &id, Some(user_id), 2, 1100).await);
+        assert!(matches!(after, ApiError::Conflict(_)), "{after:?}");
+        let session = repo.get_session(&id).await.unwrap().unwrap();
+        assert!(session.completed_at.is_some());
+        assert_eq!(session.score, Some(completed.score));
+    }
+
+    #[sqlx::test]
+    async fn anonymous_quizzes_are_scored_without_a_result(db: PgPool) {
+        let state = AppState::for_tests(db.clone());
+        let id = insert_session(&state, None).await;
+
+        for (position, taxon_id) in [(0, 1000), (1, 1010)] {
+            answer(&state, &id, None, position, taxon_id).await.unwrap();
+        }
+        let last = answer(&state, &id, None, 2, 1100).await.unwrap();
+
+        let completed = last.completed.expect("quiz completed");
+        assert_eq!(completed.score, 1.0);
+        assert!(completed.result_id.is_none());
+        let results: i64 = sqlx::query_scalar("SELECT count(*) FROM quiz_results")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(results, 0);
+    }
+
+    /// Species `id` of genus 100 or 101 in family 10, with its ancestors'
+    /// common names in English, or French for `fr`
+    fn species_taxon(id: i64, locale: &str) -> serde_json::Value {
+        let name = |en: &str, fr: &str| if locale == "fr" { fr } else { en }.to_string();
+        let ancestor = |id: i64, rank: &str, common_name: String| {
+            json!({"id": id, "name": format!("taxon {id}"), "rank": rank,
+                   "preferred_common_name": common_name})
+        };
+        let genus = id / 10;
+        json!({
+            "id": id,
+            "name": format!("taxon {id}"),
+            "rank": "species",
+            "ancestor_ids": [1, 10, genus, id],
+            "ancestors": [
+                ancestor(1, "kingdom", name("Animals", "Animaux")),
+                ancestor(10, "family", name("Crows", "Corvidés")),
+                if genus == 100 {
+                    ancestor(100, "genus", name("Ravens", "Corbeaux"))
+                } else {
+                    ancestor(101, "genus", name("Jays", "Geais"))
+                },
+            ],
+        })
+    }
+
+    #[poem::handler]
+    fn species_counts() -> poem::web::Json<serde_json::Value> {
+        let species = |id: i64, count: i64| {
+            json!({"count": count, "taxon": {"id": id, "name": format!("taxon {id}"),
+                   "rank": "species", "ancestor_ids": [1, 10, id / 10, id]}})
+        };
+        poem::web::Json(json!({
+            "total_results": 3,
+            "results": [species(1000, 5), species(1001, 3), species(1010, 2)],
+        }))
+    }
+
+    #[poem::handler]
+    fn taxa_show(
+        poem::web::Path(ids): poem::web::Path<String>,
+        poem::web::Query(query): poem::web::Query<HashMap<String, String>>,
+    ) -> poem::web::Json<serde_json::Value> {
+        let locale = query.get("locale").map_or("en", String::as_str);
+        let results: Vec<_> = ids
+            .split(',')
+            .map(|id| species_taxon(id.parse().unwrap(), locale))
+            .collect();
+        poem::web::Json(json!({"total_results": results.len(), "results": results}))
+    }
+
+    /// State whose iNaturalist client talks to a fake serving the
+    /// checklist and taxa above
+    async fn state_with_fake_inat(db: PgPool) -> AppState {
+        use poem::listener::{Acceptor, Listener, TcpListener};
+
+        let acceptor = TcpListener::bind("127.0.0.1:0")
+            .into_acceptor()
+            .await
+            .unwrap();
+        let addr = *acceptor.local_addr()[0].as_socket_addr().unwrap();
+        let app = poem::Route::new()
+            .at("/observations/species_counts", poem::get(species_counts))
+            .at("/taxa/:ids", poem::get(taxa_show));
+        tokio::spawn(poem::Server::new_with_acceptor(acceptor).run(app));
+
+        let mut state = AppState::for_tests(db);
+        state.config.inat_api_url = format!("http://{addr}");
+        state.inat = InatClient::new(&state.config, state.redis.clone()).unwrap();
+        state
+    }
+
+    async fn genus_pool(db: PgPool, locale: Option<&str>) -> HashMap<i64, Candidate> {
+        let state = state_with_fake_inat(db).await;
+        let params = QuizParams {
+            rank: "genus".to_string(),
+            locale: locale.map(str::to_string),
+            ..params(10, Difficulty::Medium)
+        };
+        answer_pool(&state, &params).await.unwrap()
+    }
+
+    #[sqlx::test]
+    async fn genus_pools_have_common_names(db: PgPool) {
+        let pool = genus_pool(db, None).await;
+
+        assert_eq!(pool.len(), 2);
+        assert_eq!(pool[&100].count, 8);
+        assert_eq!(pool[&100].lineage, [1, 10, 100]);
+        assert_eq!(pool[&100].choice.common_name.as_deref(), Some("Ravens"));
+        assert_eq!(pool[&101].choice.common_name.as_deref(), Some("Jays"));
+    }
+
+    #[sqlx::test]
+    async fn genus_pools_use_the_quiz_locale(db: PgPool) {
+        let pool = genus_pool(db, Some("fr")).await;
+
+        assert_eq!(pool[&100].choice.common_name.as_deref(), Some("Corbeaux"));
+        assert_eq!(pool[&101].choice.common_name.as_deref(), Some("Geais"));
+    }
+}
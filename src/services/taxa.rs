@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::{TimeDelta, Utc};
+use sqlx::types::Json;
+use tracing::{info, warn};
+
+use crate::clients::inat::{InatClient, InatTaxon, MAX_PER_PAGE};
+use crate::error::ApiResult;
+use crate::repos::taxon_repo::{TaxonRepo, TaxonRow};
+use crate::state::AppState;
+
+/// Locale used for common names when none is asked for, matching
+/// iNaturalist's default
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// How long a mirrored taxon is served before it's fetched again on demand
+pub const MAX_AGE: TimeDelta = TimeDelta::days(30);
+
+/// A taxon from the local mirror, with whichever of its ancestors are stored
+pub struct MirroredTaxon {
+    pub taxon: TaxonRow,
+    pub ancestors: Vec<TaxonRow>,
+}
+
+/// Look a taxon up in the local mirror, fetching it (and storing its
+/// ancestors) from iNaturalist if it's missing or older than [`MAX_AGE`].
+/// A stale copy is still returned when iNaturalist can't be reached.
+pub async fn get_taxon(
+    state: &AppState,
+    id: i64,
+    locale: Option<&str>,
+) -> ApiResult<Option<MirroredTaxon>> {
+    let repo = TaxonRepo::new(state.db.clone());
+
+    let stored = repo.get(id).await?;
+    let fresh = stored
+        .as_ref()
+        .and_then(|t| t.fetched_at)
+        .is_some_and(|at| Utc::now() - at < MAX_AGE);
+
+    if !fresh {
+        match state.inat.fetch_taxon(id, locale).await {
+            Ok(Some(taxon)) => repo.upsert(&rows_from_inat(&taxon, locale)).await?,
+            Ok(None) => return Ok(None),
+            Err(e) if stored.is_some() => {
+                warn!(taxon_id = id, "serving stale taxon: {e:#}");
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let Some(taxon) = repo.get(id).await? else {
+        return Ok(None);
+    };
+    let ancestors = repo.get_many(&taxon.ancestor_ids()).await?;
+    Ok(Some(MirroredTaxon { taxon, ancestors }))
+}
+
+/// Re-fetch the given taxa from iNaturalist, in batches, and store them.
+/// Ancestors get their common name in `locale`, or [`DEFAULT_LOCALE`].
+/// Returns how many were updated.
+pub async fn refresh_taxa(
+    repo: &TaxonRepo,
+    inat: &InatClient,
+    ids: &[i64],
+    locale: Option<&str>,
+) -> Result<usize> {
+    let mut updated = 0;
+    for batch in ids.chunks(MAX_PER_PAGE) {
+        let taxa = inat.fetch_taxa(batch, locale).await?;
+        let rows: Vec<_> = taxa
+            .iter()
+            .flat_map(|taxon| rows_from_inat(taxon, locale))
+            .collect();
+        repo.upsert(&rows).await?;
+
+        updated += taxa.len();
+        info!(updated, total = ids.len(), "refreshed taxa");
+    }
+    Ok(updated)
+}
+
+/// Rows for a taxon and its ancestors. Ancestors are partial records, with
+/// only the common name in `locale` ([`DEFAULT_LOCALE`] if `None`) and no
+/// `fetched_at`.
+fn rows_from_inat(taxon: &InatTaxon, locale: Option<&str>) -> Vec<TaxonRow> {
+    let ancestor_ids = taxon.ancestor_ids();
+    let now = Utc::now();
+
+    let ancestors = taxon.ancestors.iter().flatten().filter_map(|a| {
+        // its own lineage is a prefix of the taxon's
+        let depth = ancestor_ids.iter().position(|&id| id == a.id)?;
+        let lineage = &ancestor_ids[..=depth];
+        Some(row(a, lineage, common_names(a, locale), None))
+    });
+
+    let mut lineage = ancestor_ids.clone();
+    lineage.push(taxon.id);
+    let this = row(taxon, &lineage, common_names(taxon, locale), Some(now));
+
+    ancestors.chain([this]).collect()
+}
+
+fn row(
+    taxon: &InatTaxon,
+    lineage: &[i64],
+    common_names: HashMap<String, String>,
+    fetched_at: Option<chrono::DateTime<Utc>>,
+) -> TaxonRow {
+    let path = lineage
+        .iter()
+        .map(i64::to_string)
+        .collect::<Vec<_>>()
+        .join(".");
+    let parent_id = lineage.len().checked_sub(2).map(|i| lineage[i]);
+
+    TaxonRow {
+        id: taxon.id,
+        parent_id,
+        path,
+        rank: taxon.rank.clone(),
+        rank_level: taxon.rank_level,
+        name: taxon.name.clone(),
+        common_names: Json(common_names),
+        default_photo: taxon.default_photo.clone().map(Json),
+        iconic_taxon_name: taxon.iconic_taxon_name.clone(),
+        observations_count: taxon.observations_count,
+        wikipedia_url: taxon.wikipedia_url.clone(),
+        is_active: taxon.is_active.unwrap_or(true),
+        fetched_at,
+    }
+}
+
+/// Common names by locale. The first valid name per locale wins, except that
+/// the preferred name for the requested locale overrides it. iNaturalist
+/// picks the preferred name in [`DEFAULT_LOCALE`] when no locale is given.
+fn common_names(taxon: &InatTaxon, locale: Option<&str>) -> HashMap<String, String> {
+    let mut names = HashMap::new();
+    for name in taxon.names.iter().flatten() {
+        if name.lexicon.as_deref() == Some("scientific-names") || name.is_valid == Some(false) {
+            continue;
+        }
+        if let Some(locale) = &name.locale {
+            names
+                .entry(locale.to_lowercase())
+                .or_insert_with(|| name.name.clone());
+        }
+    }
+    if let Some(preferred) = &taxon.preferred_common_name {
+        let locale = locale.unwrap_or(DEFAULT_LOCALE);
+        names.insert(locale.to_lowercase(), preferred.clone());
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn raven() -> InatTaxon {
+        serde_json::from_value(json!({
+            "id": 8010,
+            "name": "Corvus corax",
+            "rank": "species",
+            "preferred_common_name": "Grand Corbeau",
+            "ancestor_ids": [1, 8000, 8010],
+            "ancestors": [
+                {"id": 1, "name": "Animalia", "rank": "kingdom"},
+                {
+                    "id": 8000,
+                    "name": "Corvidae",
+                    "rank": "family",
+                    "preferred_common_name": "Crows, Jays, and Magpies",
+                },
+            ],
+        }))
+        .unwrap()
+    }
+
+    fn names(rows: &[TaxonRow], id: i64) -> &HashMap<String, String> {
+        &rows.iter().find(|r| r.id == id).unwrap().common_names.0
+    }
+
+    #[test]
+    fn preferred_names_default_to_english() {
+        let mut taxon = raven();
+        taxon.preferred_common_name = Some("Common Raven".to_string());
+        let rows = rows_from_inat(&taxon, None);
+
+        assert_eq!(names(&rows, 8010)["en"], "Common Raven");
+        assert_eq!(names(&rows, 8000)["en"], "Crows, Jays, and Magpies");
+        assert!(names(&rows, 1).is_empty());
+        assert_eq!(rows.iter().find(|r| r.id == 8000).unwrap().path, "1.8000");
+    }
+
+    #[test]
+    fn preferred_names_are_stored_under_the_requested_locale() {
+        let rows = rows_from_inat(&raven(), Some("FR"));
+
+        assert_eq!(names(&rows, 8010).len(), 1);
+        assert_eq!(names(&rows, 8010)["fr"], "Grand Corbeau");
+    }
+}
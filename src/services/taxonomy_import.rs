@@ -0,0 +1,574 @@
+//! Import of the iNaturalist taxonomy Darwin Core Archive
+//! (<https://www.inaturalist.org/taxa/inaturalist-taxonomy.dwca.zip>) into the
+//! `taxa` table.
+//!
+//! Files are parsed on a blocking thread and streamed to Postgres in batches.
+//! After each batch a checkpoint is saved, so an interrupted import picks up
+//! where it stopped. Upserts are idempotent, so re-running is always safe.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow, bail};
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::repos::taxon_repo::{ImportCheckpoint, ImportedTaxon, TaxonRepo};
+
+const TAXA_FILE: &str = "taxa.csv";
+const VERNACULAR_PREFIX: &str = "VernacularNames-";
+
+/// Log progress every this many rows
+const PROGRESS_EVERY: u64 = 50_000;
+
+/// Taxon trees are shallow; anything deeper is a cycle in the data
+const MAX_DEPTH: usize = 100;
+
+/// Counts of what an import wrote
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub taxa: u64,
+    pub common_names: u64,
+}
+
+enum Batch {
+    Taxa {
+        file: String,
+        last_row: u64,
+        rows: Vec<ImportedTaxon>,
+    },
+    Names {
+        file: String,
+        last_row: u64,
+        /// (taxon id, locale, name)
+        names: Vec<(i64, String, String)>,
+    },
+}
+
+/// Import the archive at `path`, either the `.zip` as downloaded or a
+/// directory it was extracted to. With `restart`, any saved checkpoint is
+/// ignored and the import starts from the beginning.
+pub async fn import_dwca(
+    repo: &TaxonRepo,
+    path: &Path,
+    batch_size: usize,
+    restart: bool,
+) -> Result<ImportSummary> {
+    let source = format!("dwca:{}", path.display());
+    let archive = Archive::open(path)?;
+    let files = archive.import_order()?;
+
+    let checkpoint = if restart {
+        None
+    } else {
+        repo.get_import_checkpoint(&source).await?
+    };
+    if let Some(cp) = &checkpoint {
+        info!(file = cp.file, row = cp.row, "resuming taxonomy import");
+    }
+
+    let (tx, mut rx) = mpsc::channel(4);
+    let producer = tokio::task::spawn_blocking(move || {
+        produce(&archive, &files, checkpoint.as_ref(), batch_size, &tx)
+    });
+
+    let mut summary = ImportSummary::default();
+    while let Some(batch) = rx.recv().await {
+        let (file, last_row) = match batch {
+            Batch::Taxa {
+                file,
+                last_row,
+                rows,
+            } => {
+                repo.upsert_imported(&rows).await?;
+                summary.taxa += rows.len() as u64;
+                (file, last_row)
+            }
+            Batch::Names {
+                file,
+                last_row,
+                names,
+            } => {
+                repo.merge_common_names(&names).await?;
+                summary.common_names += names.len() as u64;
+                (file, last_row)
+            }
+        };
+        repo.save_import_checkpoint(&source, &file, last_row as i64)
+            .await?;
+    }
+    // the channel closes early if the producer failed
+    producer.await??;
+
+    repo.delete_import_checkpoint(&source).await?;
+    Ok(summary)
+}
+
+/// Parses every file in order and sends batches of rows
+fn produce(
+    archive: &Archive,
+    files: &[String],
+    checkpoint: Option<&ImportCheckpoint>,
+    batch_size: usize,
+    tx: &mpsc::Sender<Batch>,
+) -> Result<()> {
+    // index of the first file with rows left, and rows to skip in it
+    let (start, skip) = match checkpoint {
+        Some(cp) => match files.iter().position(|f| *f == cp.file) {
+            Some(i) => (i, cp.row.max(0) as u64),
+            None => bail!(
+                "checkpoint refers to {}, which isn't in the archive",
+                cp.file
+            ),
+        },
+        None => (0, 0),
+    };
+
+    for (i, file) in files.iter().enumerate().skip(start) {
+        let skip = if i == start { skip } else { 0 };
+        if file == TAXA_FILE {
+            import_taxa(archive, skip, batch_size, tx)?;
+        } else {
+            import_names(archive, file, skip, batch_size, tx)?;
+        }
+    }
+    Ok(())
+}
+
+fn import_taxa(
+    archive: &Archive,
+    skip: u64,
+    batch_size: usize,
+    tx: &mpsc::Sender<Batch>,
+) -> Result<()> {
+    // paths need every taxon's parent, so read the parent links first
+    let parents = archive.with_csv(TAXA_FILE, |reader| {
+        let cols = TaxaColumns::find(reader.headers()?)?;
+        let mut parents: HashMap<i64, Option<i64>> = HashMap::new();
+        for record in reader.records() {
+            let (id, parent_id) = cols.ids(&record?)?;
+            parents.insert(id, parent_id);
+        }
+        Ok(parents)
+    })?;
+    info!(taxa = parents.len(), "read taxon tree");
+
+    archive.with_csv(TAXA_FILE, |reader| {
+        let cols = TaxaColumns::find(reader.headers()?)?;
+        let mut rows = Vec::with_capacity(batch_size);
+        let mut row_number = 0;
+        for record in reader.records() {
+            let record = record?;
+            row_number += 1;
+            if row_number <= skip {
+                continue;
+            }
+
+            let (id, parent_id) = cols.ids(&record)?;
+            let rank = cols.rank(&record);
+            rows.push(ImportedTaxon {
+                id,
+                parent_id,
+                path: path_to(id, &parents)?,
+                rank_level: rank.as_deref().and_then(rank_level),
+                rank,
+                name: cols.name(&record)?,
+            });
+
+            if rows.len() == batch_size {
+                send_taxa(tx, row_number, &mut rows)?;
+            }
+            if row_number % PROGRESS_EVERY == 0 {
+                info!(file = TAXA_FILE, rows = row_number, "importing taxonomy");
+            }
+        }
+        if !rows.is_empty() {
+            send_taxa(tx, row_number, &mut rows)?;
+        }
+        Ok(())
+    })
+}
+
+fn send_taxa(tx: &mpsc::Sender<Batch>, last_row: u64, rows: &mut Vec<ImportedTaxon>) -> Result<()> {
+    tx.blocking_send(Batch::Taxa {
+        file: TAXA_FILE.to_string(),
+        last_row,
+        rows: std::mem::take(rows),
+    })
+    .map_err(|_| anyhow!("import aborted"))
+}
+
+fn import_names(
+    archive: &Archive,
+    file: &str,
+    skip: u64,
+    batch_size: usize,
+    tx: &mpsc::Sender<Batch>,
+) -> Result<()> {
+    archive.with_csv(file, |reader| {
+        let headers = reader.headers()?.clone();
+        let id_col = column(&headers, "id", file)?;
+        let name_col = column(&headers, "vernacularName", file)?;
+        let lang_col = column(&headers, "language", file)?;
+
+        let mut names = Vec::with_capacity(batch_size);
+        let mut row_number = 0;
+        for record in reader.records() {
+            let record = record?;
+            row_number += 1;
+            if row_number <= skip {
+                continue;
+            }
+
+            let (Some(id), Some(name), Some(lang)) = (
+                record.get(id_col).and_then(parse_id),
+                record
+                    .get(name_col)
+                    .map(str::trim)
+                    .filter(|n| !n.is_empty()),
+                record
+                    .get(lang_col)
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty()),
+            ) else {
+                continue;
+            };
+            names.push((id, lang.to_lowercase(), name.to_string()));
+
+            if names.len() == batch_size {
+                send_names(tx, file, row_number, &mut names)?;
+            }
+            if row_number % PROGRESS_EVERY == 0 {
+                info!(file, rows = row_number, "importing common names");
+            }
+        }
+        if !names.is_empty() {
+            send_names(tx, file, row_number, &mut names)?;
+        }
+        Ok(())
+    })
+}
+
+fn send_names(
+    tx: &mpsc::Sender<Batch>,
+    file: &str,
+    last_row: u64,
+    names: &mut Vec<(i64, String, String)>,
+) -> Result<()> {
+    tx.blocking_send(Batch::Names {
+        file: file.to_string(),
+        last_row,
+        names: std::mem::take(names),
+    })
+    .map_err(|_| anyhow!("import aborted"))
+}
+
+/// Dot-separated ids from the root down to `id`. Stops at the first parent
+/// missing from the archive.
+fn path_to(id: i64, parents: &HashMap<i64, Option<i64>>) -> Result<String> {
+    let mut lineage = vec![id];
+    let mut current = id;
+    while let Some(Some(parent)) = parents.get(&current) {
+        if lineage.len() > MAX_DEPTH {
+            bail!("taxon {id} has a cycle in its ancestry");
+        }
+        lineage.push(*parent);
+        current = *parent;
+    }
+    lineage.reverse();
+    Ok(lineage
+        .iter()
+        .map(i64::to_string)
+        .collect::<Vec<_>>()
+        .join("."))
+}
+
+/// Numeric levels iNaturalist uses for the common ranks
+fn rank_level(rank: &str) -> Option<f64> {
+    let level = match rank {
+        "stateofmatter" => 100.0,
+        "kingdom" => 70.0,
+        "subkingdom" => 67.0,
+        "phylum" => 60.0,
+        "subphylum" => 57.0,
+        "superclass" => 53.0,
+        "class" => 50.0,
+        "subclass" => 47.0,
+        "infraclass" => 45.0,
+        "superorder" => 43.0,
+        "order" => 40.0,
+        "suborder" => 37.0,
+        "infraorder" => 35.0,
+        "superfamily" => 33.0,
+        "epifamily" => 32.0,
+        "family" => 30.0,
+        "subfamily" => 27.0,
+        "supertribe" => 26.0,
+        "tribe" => 25.0,
+        "subtribe" => 24.0,
+        "genus" => 20.0,
+        "genushybrid" => 20.0,
+        "subgenus" => 15.0,
+        "section" => 13.0,
+        "subsection" => 12.0,
+        "complex" => 11.0,
+        "species" => 10.0,
+        "hybrid" => 10.0,
+        "subspecies" => 5.0,
+        "variety" => 5.0,
+        "form" => 5.0,
+        "infrahybrid" => 5.0,
+        _ => return None,
+    };
+    Some(level)
+}
+
+/// Ids appear either as plain numbers or as taxon URLs ending in the number
+fn parse_id(value: &str) -> Option<i64> {
+    value.trim().rsplit('/').next()?.parse().ok()
+}
+
+fn column(headers: &csv::StringRecord, name: &str, file: &str) -> Result<usize> {
+    headers
+        .iter()
+        .position(|h| h == name)
+        .ok_or_else(|| anyhow!("{file} has no {name} column"))
+}
+
+struct TaxaColumns {
+    id: usize,
+    parent: usize,
+    name: usize,
+    rank: usize,
+}
+
+impl TaxaColumns {
+    fn find(headers: &csv::StringRecord) -> Result<Self> {
+        Ok(Self {
+            id: column(headers, "id", TAXA_FILE)?,
+            parent: column(headers, "parentNameUsageID", TAXA_FILE)?,
+            name: column(headers, "scientificName", TAXA_FILE)?,
+            rank: column(headers, "taxonRank", TAXA_FILE)?,
+        })
+    }
+
+    fn ids(&self, record: &csv::StringRecord) -> Result<(i64, Option<i64>)> {
+        let id = record
+            .get(self.id)
+            .and_then(parse_id)
+            .with_context(|| format!("invalid taxon id in {TAXA_FILE}: {record:?}"))?;
+        let parent_id = record.get(self.parent).and_then(parse_id);
+        Ok((id, parent_id))
+    }
+
+    fn name(&self, record: &csv::StringRecord) -> Result<String> {
+        record
+            .get(self.name)
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .map(str::to_string)
+            .with_context(|| format!("missing scientificName in {TAXA_FILE}: {record:?}"))
+    }
+
+    fn rank(&self, record: &csv::StringRecord) -> Option<String> {
+        record
+            .get(self.rank)
+            .map(|r| r.trim().to_lowercase())
+            .filter(|r| !r.is_empty())
+    }
+}
+
+/// The archive as downloaded, or extracted into a directory
+enum Archive {
+    Zip(PathBuf),
+    Dir(PathBuf),
+}
+
+impl Archive {
+    fn open(path: &Path) -> Result<Self> {
+        if path.is_dir() {
+            Ok(Archive::Dir(path.to_path_buf()))
+        } else if path.is_file() {
+            Ok(Archive::Zip(path.to_path_buf()))
+        } else {
+            bail!("{} does not exist", path.display())
+        }
+    }
+
+    /// `taxa.csv` first, since names refer to taxa, then the vernacular name
+    /// files in a stable order
+    fn import_order(&self) -> Result<Vec<String>> {
+        let names = self.file_names()?;
+        if !names.iter().any(|n| n == TAXA_FILE) {
+            bail!("archive has no {TAXA_FILE}");
+        }
+        let mut vernacular: Vec<_> = names
+            .into_iter()
+            .filter(|n| n.starts_with(VERNACULAR_PREFIX) && n.ends_with(".csv"))
+            .collect();
+        vernacular.sort();
+
+        let mut files = vec![TAXA_FILE.to_string()];
+        files.extend(vernacular);
+        Ok(files)
+    }
+
+    fn file_names(&self) -> Result<Vec<String>> {
+        match self {
+            Archive::Zip(path) => {
+                let zip = zip::ZipArchive::new(File::open(path)?)?;
+                Ok(zip.file_names().map(base_name).collect())
+            }
+            Archive::Dir(path) => {
+                let mut names = Vec::new();
+                for entry in std::fs::read_dir(path)? {
+                    names.push(entry?.file_name().to_string_lossy().into_owned());
+                }
+                Ok(names)
+            }
+        }
+    }
+
+    /// Calls `f` with a CSV reader streaming the named file
+    fn with_csv<T>(
+        &self,
+        name: &str,
+        f: impl FnOnce(&mut csv::Reader<&mut dyn Read>) -> Result<T>,
+    ) -> Result<T> {
+        let builder = || {
+            let mut builder = csv::ReaderBuilder::new();
+            builder.flexible(true);
+            builder
+        };
+        match self {
+            Archive::Zip(path) => {
+                let mut zip = zip::ZipArchive::new(File::open(path)?)?;
+                let full_name = zip
+                    .file_names()
+                    .find(|n| base_name(n) == name)
+                    .map(str::to_string)
+                    .with_context(|| format!("archive has no {name}"))?;
+                let mut entry = zip.by_name(&full_name)?;
+                f(&mut builder().from_reader(&mut entry as &mut dyn Read))
+            }
+            Archive::Dir(path) => {
+                let mut file = File::open(path.join(name))
+                    .with_context(|| format!("failed to open {name}"))?;
+                f(&mut builder().from_reader(&mut file as &mut dyn Read))
+            }
+        }
+    }
+}
+
+fn base_name(name: &str) -> String {
+    name.rsplit('/').next().unwrap_or(name).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use sqlx::PgPool;
+
+    use super::import_dwca;
+    use crate::repos::taxon_repo::TaxonRepo;
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/dwca-sample");
+
+    /// A copy of the fixture archive in which Corvus corax is listed twice,
+    /// the second time under a corrected name
+    fn archive_with_duplicate() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dwca-sample-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        for entry in std::fs::read_dir(FIXTURE).unwrap() {
+            let entry = entry.unwrap();
+            std::fs::copy(entry.path(), dir.join(entry.file_name())).unwrap();
+        }
+        let taxa = dir.join("taxa.csv");
+        let mut csv = std::fs::read_to_string(&taxa).unwrap();
+        csv.push_str(
+            "8010,https://www.inaturalist.org/taxa/8010,8010,https://www.inaturalist.org/taxa/8021,\
+             Animalia,Chordata,Aves,Passeriformes,Corvidae,Corvus,corax,,2024-02-01T00:00:00Z,\
+             Corvus corax corax,species,https://www.inaturalist.org/taxa/8010\n",
+        );
+        std::fs::write(taxa, csv).unwrap();
+        dir
+    }
+
+    fn source(path: &Path) -> String {
+        format!("dwca:{}", path.display())
+    }
+
+    #[sqlx::test]
+    async fn imports_and_resumes_the_fixture_archive(pool: PgPool) {
+        let repo = TaxonRepo::new(pool.clone());
+        let archive = archive_with_duplicate();
+
+        // the whole file in one batch, duplicate included
+        let summary = import_dwca(&repo, &archive, 100, false).await.unwrap();
+        assert_eq!(summary.taxa, 12);
+        assert_eq!(summary.common_names, 10);
+
+        let crow = repo.get(8088).await.unwrap().unwrap();
+        assert_eq!(crow.path, "48460.1.2.355675.3.7251.7823.8021.8088");
+        assert_eq!(crow.parent_id, Some(8021));
+        assert_eq!(crow.rank.as_deref(), Some("species"));
+        assert_eq!(crow.rank_level, Some(10.0));
+        assert!(crow.fetched_at.is_none());
+        assert_eq!(repo.get(48460).await.unwrap().unwrap().path, "48460");
+        assert_eq!(
+            repo.get(48460).await.unwrap().unwrap().rank_level,
+            Some(100.0)
+        );
+        assert_eq!(
+            repo.get(8021).await.unwrap().unwrap().rank_level,
+            Some(20.0)
+        );
+
+        let raven = repo.get(8010).await.unwrap().unwrap();
+        assert_eq!(raven.name, "Corvus corax corax");
+        assert_eq!(raven.common_names.0["en"], "Common Raven");
+        assert_eq!(raven.common_names.0["es"], "Cuervo Grande");
+        // the first name per locale is kept
+        let corvids = repo.get(7823).await.unwrap().unwrap();
+        assert_eq!(corvids.common_names.0.len(), 1);
+        assert_eq!(corvids.common_names.0["en"], "Crows and Jays");
+
+        assert!(
+            repo.get_import_checkpoint(&source(&archive))
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        // an import interrupted after the first five taxa
+        sqlx::query("DELETE FROM taxa")
+            .execute(&pool)
+            .await
+            .unwrap();
+        repo.save_import_checkpoint(&source(&archive), "taxa.csv", 5)
+            .await
+            .unwrap();
+
+        let summary = import_dwca(&repo, &archive, 2, false).await.unwrap();
+        assert_eq!(summary.taxa, 7);
+
+        assert!(repo.get(3).await.unwrap().is_none());
+        let order = repo.get(7251).await.unwrap().unwrap();
+        assert_eq!(order.path, "48460.1.2.355675.3.7251");
+        assert_eq!(order.rank_level, Some(40.0));
+        // names of taxa that weren't re-imported are skipped
+        let raven = repo.get(8010).await.unwrap().unwrap();
+        assert_eq!(raven.common_names.0["es"], "Cuervo Grande");
+        assert!(
+            repo.get_import_checkpoint(&source(&archive))
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        std::fs::remove_dir_all(archive).unwrap();
+    }
+}
@@ -2,12 +2,17 @@ use anyhow::Result;
 use chrono::{DateTime, Utc};
 use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
+use tracing::instrument;
 
+use crate::config::Config;
+use crate::metrics::METRICS;
 use crate::services::rand::generate_random_id;
 
 #[derive(Clone)]
 pub struct SessionStore {
     client: redis::Client,
+    session_ttl_secs: u64,
+    oauth_state_ttl_secs: u64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -17,8 +22,12 @@ pub struct SessionData {
 }
 
 impl SessionStore {
-    pub fn new(client: redis::Client) -> Self {
-        Self { client }
+    pub fn new(client: redis::Client, config: &Config) -> Self {
+        Self {
+            client,
+            session_ttl_secs: config.session_ttl_secs,
+            oauth_state_ttl_secs: config.oauth_state_ttl_secs,
+        }
     }
 
     fn session_key(id: &str) -> String {
@@ -29,7 +38,9 @@ impl SessionStore {
         format!("oauth_state:{state}")
     }
 
+    #[instrument(name = "redis.create_session", skip_all)]
     pub async fn create_session(&self, user_id: i64) -> Result<String> {
+        let _timer = METRICS.redis_timer("create_session");
         let mut conn = self.client.get_multiplexed_async_connection().await?;
         let session_id = generate_random_id();
 
@@ -41,28 +52,61 @@ impl SessionStore {
         let json = serde_json::to_string(&data)?;
         let key = Self::session_key(&session_id);
 
-        // 7 days
-        let _: () = conn.set_ex(key, json, 60 * 60 * 24 * 7).await?;
+        let _: () = conn.set_ex(key, json, self.session_ttl_secs).await?;
 
         Ok(session_id)
     }
 
+    #[instrument(name = "redis.get_session", skip_all)]
     pub async fn get_session(&self, id: &str) -> Result<Option<SessionData>> {
+        let _timer = METRICS.redis_timer("get_session");
         let mut conn = self.client.get_multiplexed_async_connection().await?;
         let key = Self::session_key(id);
         let json: Option<String> = conn.get(key).await?;
         Ok(json.map(|j| serde_json::from_str(&j)).transpose()?)
     }
 
+    /// Delete every session belonging to `user_id`, returning how many were removed
+    #[instrument(name = "redis.revoke_user_sessions", skip_all)]
+    pub async fn revoke_user_sessions(&self, user_id: i64) -> Result<usize> {
+        let _timer = METRICS.redis_timer("revoke_user_sessions");
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+
+        // sessions aren't indexed by user, so walk all of them
+        let mut keys = Vec::new();
+        {
+            let mut iter = conn.scan_match::<_, String>(Self::session_key("*")).await?;
+            while let Some(key) = iter.next_item().await {
+                keys.push(key);
+            }
+        }
+
+        let mut revoked = 0;
+        for key in keys {
+            let json: Option<String> = conn.get(&key).await?;
+            let Some(json) = json else { continue };
+            let data: SessionData = serde_json::from_str(&json)?;
+            if data.user_id == user_id {
+                let _: () = conn.del(&key).await?;
+                revoked += 1;
+            }
+        }
+
+        Ok(revoked)
+    }
+
+    #[instrument(name = "redis.store_oauth_state", skip_all)]
     pub async fn store_oauth_state(&self, state: &str) -> Result<()> {
+        let _timer = METRICS.redis_timer("store_oauth_state");
         let mut conn = self.client.get_multiplexed_async_connection().await?;
         let key = Self::oauth_state_key(state);
-        // 10 minutes
-        let _: () = conn.set_ex(key, "1", 600).await?;
+        let _: () = conn.set_ex(key, "1", self.oauth_state_ttl_secs).await?;
         Ok(())
     }
 
+    #[instrument(name = "redis.consume_oauth_state", skip_all)]
     pub async fn consume_oauth_state(&self, state: &str) -> Result<bool> {
+        let _timer = METRICS.redis_timer("consume_oauth_state");
         let mut conn = self.client.get_multiplexed_async_connection().await?;
         let key = Self::oauth_state_key(state);
         let exists: bool = conn.exists(&key).await?;
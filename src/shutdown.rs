@@ -0,0 +1,58 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use tokio::signal;
+use tracing::info;
+
+/// Shared flag flipped when the server starts shutting down, so the health
+/// check can tell the proxy to stop routing new requests here.
+#[derive(Clone, Default)]
+pub struct ShutdownState {
+    draining: Arc<AtomicBool>,
+}
+
+impl ShutdownState {
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    fn start_draining(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Resolves once SIGINT or SIGTERM is received and `delay` has passed.
+///
+/// The health check reports "draining" during the delay, giving the proxy
+/// time to notice before the listener is closed.
+pub async fn signal(state: ShutdownState, delay: Duration) {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install SIGINT handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("received SIGINT"),
+        _ = terminate => info!("received SIGTERM"),
+    }
+
+    state.start_draining();
+    if !delay.is_zero() {
+        info!("draining, closing listener in {}s", delay.as_secs());
+        tokio::time::sleep(delay).await;
+    }
+    info!("shutting down, waiting for in-flight requests");
+}
@@ -1,17 +1,43 @@
 use redis::Client;
 use sqlx::{Pool, Postgres};
 
+use crate::clients::inat::InatClient;
 use crate::config::Config;
+use crate::shutdown::ShutdownState;
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: Pool<Postgres>,
     pub redis: Client,
+    pub inat: InatClient,
     pub config: Config,
+    pub shutdown: ShutdownState,
 }
 
 impl AppState {
-    pub fn new(db: Pool<Postgres>, redis: Client, config: Config) -> Self {
-        Self { db, redis, config }
+    pub fn new(
+        db: Pool<Postgres>,
+        redis: Client,
+        inat: InatClient,
+        config: Config,
+        shutdown: ShutdownState,
+    ) -> Self {
+        Self {
+            db,
+            redis,
+            inat,
+            config,
+            shutdown,
+        }
+    }
+
+    /// State for tests against `db`. Redis points at a closed port, so the
+    /// cache and rate limiter fall back to calling iNaturalist directly.
+    #[cfg(test)]
+    pub fn for_tests(db: Pool<Postgres>) -> Self {
+        let config = Config::for_tests();
+        let redis = Client::open("redis://127.0.0.1:1").expect("valid redis url");
+        let inat = InatClient::new(&config, redis.clone()).expect("valid test config");
+        Self::new(db, redis, inat, config, ShutdownState::default())
     }
 }
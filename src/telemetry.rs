@@ -0,0 +1,274 @@
+use tracing_subscriber::Layer;
+use tracing_subscriber::filter::EnvFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::config::{AppEnv, Config};
+use crate::scrub::ScrubbingMakeWriter;
+
+/// Flushes exported traces when dropped; keep it alive for the whole process.
+#[must_use]
+pub struct TelemetryGuard {
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider.take()
+            && let Err(e) = provider.shutdown()
+        {
+            eprintln!("failed to flush traces: {e}");
+        }
+    }
+}
+
+pub fn init_tracing(config: &Config) -> anyhow::Result<TelemetryGuard> {
+    let env_filter = EnvFilter::from_default_env()
+        .add_directive("sqlx=info".parse().unwrap())
+        .add_directive("taxonia_api=debug".parse().unwrap());
+
+    let fmt_layer = if config.app_env == AppEnv::Production {
+        tracing_subscriber::fmt::layer()
+            .with_writer(ScrubbingMakeWriter::new(std::io::stdout))
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_span_events(tracing_subscriber::fmt::format::FmtSpan::NONE)
+            .boxed()
+    } else {
+        tracing_subscriber::fmt::layer()
+            .with_writer(ScrubbingMakeWriter::new(std::io::stdout))
+            .pretty()
+            .boxed()
+    };
+
+    #[cfg(feature = "otel")]
+    {
+        let (otel_layer, provider) = match &config.otel_endpoint {
+            Some(endpoint) => {
+                let (layer, provider) = otel::layer(config, endpoint)?;
+                (Some(layer), Some(provider))
+            }
+            None => (None, None),
+        };
+
+        // the otel layer is typed against the bare registry, so it goes first
+        tracing_subscriber::registry()
+            .with(otel_layer)
+            .with(env_filter)
+            .with(fmt_layer)
+            .init();
+
+        Ok(TelemetryGuard { provider })
+    }
+
+    #[cfg(not(feature = "otel"))]
+    {
+        tracing_subscriber::registry()
+            .with(env_filter)
+            .with(fmt_layer)
+            .init();
+
+        if config.otel_endpoint.is_some() {
+            tracing::warn!("OTEL_ENDPOINT is set, but this build lacks the `otel` feature");
+        }
+
+        Ok(TelemetryGuard {})
+    }
+}
+
+#[cfg(feature = "otel")]
+pub mod otel {
+    use opentelemetry::global;
+    use opentelemetry::propagation::{Extractor, Injector};
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::Resource;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::{Sampler, SdkTracerProvider};
+    use tracing::Span;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::Registry;
+
+    use crate::config::Config;
+
+    pub(super) fn layer(
+        config: &Config,
+        endpoint: &str,
+    ) -> anyhow::Result<(
+        tracing_opentelemetry::OpenTelemetryLayer<Registry, opentelemetry_sdk::trace::Tracer>,
+        SdkTracerProvider,
+    )> {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()?;
+
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                config.otel_sample_ratio,
+            ))))
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(config.otel_service_name.clone())
+                    .build(),
+            )
+            .build();
+
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        global::set_tracer_provider(provider.clone());
+
+        let tracer = provider.tracer("taxonia_api");
+        Ok((tracing_opentelemetry::layer().with_tracer(tracer), provider))
+    }
+
+    /// Continue the trace from an incoming `traceparent` header, if any
+    pub fn set_parent_from_headers(span: &Span, headers: &poem::http::HeaderMap) {
+        let cx = global::get_text_map_propagator(|p| p.extract(&HeaderExtractor(headers)));
+        let _ = span.set_parent(cx);
+    }
+
+    /// Add `traceparent` for the current span to an outgoing request
+    pub fn inject_headers(headers: &mut reqwest::header::HeaderMap) {
+        let cx = Span::current().context();
+        global::get_text_map_propagator(|p| p.inject_context(&cx, &mut HeaderInjector(headers)));
+    }
+
+    struct HeaderExtractor<'a>(&'a poem::http::HeaderMap);
+
+    impl Extractor for HeaderExtractor<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key).and_then(|v| v.to_str().ok())
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            self.0.keys().map(|k| k.as_str()).collect()
+        }
+    }
+
+    struct HeaderInjector<'a>(&'a mut reqwest::header::HeaderMap);
+
+    impl Injector for HeaderInjector<'_> {
+        fn set(&mut self, key: &str, value: String) {
+            if let (Ok(name), Ok(value)) = (
+                reqwest::header::HeaderName::from_bytes(key.as_bytes()),
+                reqwest::header::HeaderValue::from_str(&value),
+            ) {
+                self.0.insert(name, value);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "otel"))]
+mod tests {
+    use opentelemetry::global;
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider, SpanData};
+    use poem::http::StatusCode;
+    use poem::web::Data;
+    use poem::{Endpoint, EndpointExt, Request, Route, get, handler};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use crate::clients::inat::InatClient;
+    use crate::config::Config;
+    use crate::request_id::RequestContext;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    #[handler]
+    async fn taxon(Data(inat): Data<&InatClient>) -> poem::Result<String> {
+        let taxa = inat
+            .fetch_taxa(&[3], None)
+            .await
+            .map_err(|e| poem::Error::from_string(e.to_string(), StatusCode::BAD_GATEWAY))?;
+        Ok(taxa.len().to_string())
+    }
+
+    /// Answers one request with an empty results page, and returns its head
+    async fn fake_inat(listener: TcpListener) -> String {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut head = Vec::new();
+        let mut buf = [0; 1024];
+        while !head.ends_with(b"\r\n\r\n") {
+            let n = socket.read(&mut buf).await.unwrap();
+            assert!(n > 0, "connection closed mid-request");
+            head.extend_from_slice(&buf[..n]);
+        }
+        let body = r#"{"total_results":0,"results":[]}"#;
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        socket.write_all(response.as_bytes()).await.unwrap();
+        String::from_utf8(head).unwrap().to_ascii_lowercase()
+    }
+
+    fn attribute(span: &SpanData, key: &str) -> Option<String> {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.as_str().into_owned())
+    }
+
+    #[tokio::test]
+    async fn exports_request_spans_and_propagates_traceparent() {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let _subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+            .set_default();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = Config::for_tests();
+        config.inat_api_url = format!("http://{}", listener.local_addr().unwrap());
+        config.inat_max_retries = 0;
+        // nothing listens here, so the rate limiter lets calls through
+        let redis = redis::Client::open("redis://127.0.0.1:1").unwrap();
+        let inat = InatClient::new(&config, redis).unwrap();
+        let upstream = tokio::spawn(fake_inat(listener));
+
+        let app = Route::new()
+            .at("/taxa/:id", get(taxon))
+            .data(inat)
+            .with(RequestContext);
+        let resp = app
+            .call(
+                Request::builder()
+                    .uri("/taxa/3".parse().unwrap())
+                    .header("traceparent", format!("00-{TRACE_ID}-00f067aa0ba902b7-01"))
+                    .finish(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let upstream_head = upstream.await.unwrap();
+        assert!(
+            upstream_head.contains(&format!("traceparent: 00-{TRACE_ID}-")),
+            "traceparent not sent upstream:\n{upstream_head}"
+        );
+
+        provider.force_flush().unwrap();
+        let spans = exporter.get_finished_spans().unwrap();
+        let request = spans
+            .iter()
+            .find(|s| s.name == "request")
+            .expect("request span exported");
+        assert_eq!(request.span_context.trace_id().to_string(), TRACE_ID);
+        assert_eq!(attribute(request, "route").as_deref(), Some("/taxa/:id"));
+        assert_eq!(attribute(request, "status").as_deref(), Some("200"));
+        assert!(spans.iter().any(|s| s.name == "inat.fetch_taxa"
+            && s.span_context.trace_id() == request.span_context.trace_id()));
+    }
+}